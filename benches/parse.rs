@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate criterion;
+extern crate syslog_rfc3164;
+
+use criterion::{black_box, Criterion, Throughput};
+use syslog_rfc3164::{is_valid_message, parse_into, parse_message, parse_message_ref};
+
+// A typical short message, and the one `parser::tests::test_other_message` parses, which has
+// structured data and a long body
+const SHORT_MESSAGE: &str = "<78>Jan 8 12:14:16 host1 CROND[123]: short message";
+const LONG_MESSAGE: &str = include_str!("../testdata/long_message.txt");
+
+// Compare the owned and borrowing parsers, parsing into a reused message, and just validating, on
+// one message, reporting throughput in bytes
+fn bench_message(c: &mut Criterion, name: &str, message: &str) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    group.bench_function("parse_message", |b| b.iter(|| parse_message(black_box(message)).unwrap()));
+    group.bench_function("parse_message_ref", |b| b.iter(|| parse_message_ref(black_box(message)).unwrap()));
+    let mut out = parse_message(message).unwrap();
+    group.bench_function("parse_into", |b| b.iter(|| parse_into(black_box(message), &mut out).unwrap()));
+    group.bench_function("is_valid_message", |b| b.iter(|| is_valid_message(black_box(message))));
+    group.finish();
+}
+
+fn bench_short(c: &mut Criterion) {
+    bench_message(c, "short", SHORT_MESSAGE);
+}
+
+fn bench_long(c: &mut Criterion) {
+    bench_message(c, "long", LONG_MESSAGE);
+}
+
+criterion_group!(benches, bench_short, bench_long);
+criterion_main!(benches);
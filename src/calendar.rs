@@ -0,0 +1,208 @@
+//! Calendar math for message timestamps
+//!
+//! This is done by `chrono` when the `chrono` feature is enabled, and by hand otherwise. Either
+//! way, timestamps go in and out as seconds since the epoch. Getting the current time needs
+//! `std`.
+
+use alloc::string::String;
+
+#[cfg(feature = "chrono")]
+use chrono::{Datelike, NaiveDate, TimeZone, Timelike, Utc};
+#[cfg(feature = "chrono")]
+use core::convert::TryFrom;
+#[cfg(feature = "std")]
+use time;
+
+use message::time_t;
+
+/// A broken-down UTC date and time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CalendarTime {
+    pub year: i32,
+    /// 1 through 12
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+}
+
+impl CalendarTime {
+    /// The current time
+    #[cfg(all(feature = "std", not(feature = "chrono")))]
+    pub fn now() -> CalendarTime {
+        CalendarTime::from(&time::now_utc())
+    }
+
+    /// The current time
+    #[cfg(all(feature = "std", feature = "chrono"))]
+    pub fn now() -> CalendarTime {
+        CalendarTime::from_epoch(Utc::now().timestamp()).expect("the current time is in range")
+    }
+
+    /// Convert seconds since the epoch into a `CalendarTime`, if it's within the representable range
+    #[cfg(not(feature = "chrono"))]
+    pub fn from_epoch(ts: time_t) -> Option<CalendarTime> {
+        let (year, month, day) = civil_from_days(ts.div_euclid(86400));
+        if year < time_t::from(i32::MIN) || year > time_t::from(i32::MAX) {
+            return None;
+        }
+        let secs = ts.rem_euclid(86400) as i32;
+        Some(CalendarTime {
+            year: year as i32,
+            month: month as i32,
+            day: day as i32,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        })
+    }
+
+    /// Convert seconds since the epoch into a `CalendarTime`, if it's within the representable range
+    #[cfg(feature = "chrono")]
+    pub fn from_epoch(ts: time_t) -> Option<CalendarTime> {
+        Utc.timestamp_opt(ts, 0).single().map(|dt| CalendarTime {
+            year: dt.year(),
+            month: dt.month() as i32,
+            day: dt.day() as i32,
+            hour: dt.hour() as i32,
+            minute: dt.minute() as i32,
+            second: dt.second() as i32,
+        })
+    }
+
+    /// Seconds since the epoch. Fields that are out of range roll over into the next larger unit,
+    /// so e.g. January 32nd is February 1st. Any `i32` year is in range, so this is always `Some`.
+    #[cfg(not(feature = "chrono"))]
+    pub fn to_epoch(self) -> Option<time_t> {
+        let months = time_t::from(self.year) * 12 + time_t::from(self.month) - 1;
+        Some(days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1, 1) * 86400
+            + time_t::from(self.day - 1) * 86400
+            + time_t::from(self.hour) * 3600
+            + time_t::from(self.minute) * 60
+            + time_t::from(self.second))
+    }
+
+    /// Seconds since the epoch, if the year is within chrono's range (about 262,000 years either
+    /// side of year 0). Fields that are out of range roll over into the next larger unit, so e.g.
+    /// January 32nd is February 1st.
+    #[cfg(feature = "chrono")]
+    pub fn to_epoch(self) -> Option<time_t> {
+        // chrono rejects out-of-range fields, so start from the first of the (normalized) month and
+        // add everything else on from there
+        let months = i64::from(self.year) * 12 + i64::from(self.month) - 1;
+        let year = i32::try_from(months.div_euclid(12)).ok()?;
+        let first = NaiveDate::from_ymd_opt(year, months.rem_euclid(12) as u32 + 1, 1)?.and_hms_opt(0, 0, 0)?;
+        Some(Utc.from_utc_datetime(&first).timestamp()
+            + time_t::from(self.day - 1) * 86400
+            + time_t::from(self.hour) * 3600
+            + time_t::from(self.minute) * 60
+            + time_t::from(self.second))
+    }
+
+    /// Format as an RFC3339 UTC timestamp, with `nanos` as fractional seconds if present
+    pub fn to_rfc3339(self, nanos: Option<u32>) -> String {
+        let mut s = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                            self.year, self.month, self.day, self.hour, self.minute, self.second);
+        if let Some(nanos) = nanos {
+            s.push_str(&format!(".{:09}", nanos));
+        }
+        s.push('Z');
+        s
+    }
+}
+
+// Days since the epoch of a (proleptic Gregorian) date, following
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+#[cfg(not(feature = "chrono"))]
+fn days_from_civil(year: time_t, month: time_t, day: time_t) -> time_t {
+    // Years start in March, so that the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The inverse of `days_from_civil`
+#[cfg(not(feature = "chrono"))]
+fn civil_from_days(days: time_t) -> (time_t, time_t, time_t) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(feature = "std")]
+impl<'a> From<&'a time::Tm> for CalendarTime {
+    fn from(tm: &'a time::Tm) -> CalendarTime {
+        let tm = tm.to_utc();
+        CalendarTime {
+            year: tm.tm_year + 1900,
+            month: tm.tm_mon + 1,
+            day: tm.tm_mday,
+            hour: tm.tm_hour,
+            minute: tm.tm_min,
+            second: tm.tm_sec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CalendarTime;
+
+    #[test]
+    fn test_epoch_round_trip() {
+        let ct = CalendarTime { year: 2017, month: 1, day: 8, hour: 12, minute: 14, second: 16 };
+        assert_eq!(ct.to_epoch(), Some(1483877656));
+        assert_eq!(CalendarTime::from_epoch(1483877656), Some(ct));
+    }
+
+    #[test]
+    fn test_to_rfc3339() {
+        let ct = CalendarTime { year: 2017, month: 1, day: 8, hour: 12, minute: 14, second: 16 };
+        assert_eq!(ct.to_rfc3339(None), "2017-01-08T12:14:16Z");
+        assert_eq!(ct.to_rfc3339(Some(1500)), "2017-01-08T12:14:16.000001500Z");
+    }
+
+    #[test]
+    fn test_to_epoch_rolls_over() {
+        let ct = CalendarTime { year: 2016, month: 12, day: 32, hour: 12, minute: 14, second: 16 };
+        assert_eq!(ct.to_epoch(), Some(1483272856));
+        let ct = CalendarTime { year: 2017, month: 13, day: 1, hour: 0, minute: 0, second: 0 };
+        assert_eq!(ct.to_epoch(), Some(1514764800));
+    }
+
+    #[test]
+    fn test_leap_days() {
+        let ct = CalendarTime { year: 2016, month: 2, day: 29, hour: 0, minute: 0, second: 0 };
+        assert_eq!(ct.to_epoch(), Some(1456704000));
+        assert_eq!(CalendarTime::from_epoch(1456704000), Some(ct));
+        let ct = CalendarTime { year: 2000, month: 2, day: 29, hour: 23, minute: 59, second: 59 };
+        assert_eq!(ct.to_epoch().and_then(CalendarTime::from_epoch), Some(ct));
+        // 1900 wasn't a leap year
+        let ct = CalendarTime { year: 1900, month: 2, day: 29, hour: 0, minute: 0, second: 0 };
+        assert_eq!(ct.to_epoch().and_then(CalendarTime::from_epoch).map(|ct| (ct.month, ct.day)), Some((3, 1)));
+        let ct = CalendarTime { year: 1969, month: 12, day: 31, hour: 23, minute: 59, second: 59 };
+        assert_eq!(ct.to_epoch(), Some(-1));
+        assert_eq!(CalendarTime::from_epoch(-1), Some(ct));
+    }
+
+    #[test]
+    fn test_to_epoch_out_of_range() {
+        let ct = CalendarTime { year: i32::MAX, month: 12, day: 31, hour: 23, minute: 59, second: 59 };
+        assert_eq!(ct.to_epoch().is_some(), cfg!(not(feature = "chrono")));
+        let ct = CalendarTime { year: 300000, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+        assert_eq!(ct.to_epoch().is_some(), cfg!(not(feature = "chrono")));
+        let ct = CalendarTime { year: i32::MIN, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+        assert_eq!(ct.to_epoch().is_some(), cfg!(not(feature = "chrono")));
+    }
+}
@@ -1,6 +1,10 @@
-use serde::{Serializer, Serialize};
+use core::fmt;
+use core::str::FromStr;
 
-#[derive(Copy,Clone,Debug,PartialEq)]
+use serde::{Deserialize, Deserializer, Serializer, Serialize};
+use serde::de::{self, Unexpected, Visitor};
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
 #[allow(non_camel_case_types)]
 /// Syslog facilities. Taken From RFC 5424, but I've heard that some platforms mix these around.
 /// Names are from Linux.
@@ -31,6 +35,17 @@ pub enum SyslogFacility {
     LOG_LOCAL7 = 23,
 }
 
+const ALL_FACILITIES: [SyslogFacility; 24] = [
+    SyslogFacility::LOG_KERN, SyslogFacility::LOG_USER, SyslogFacility::LOG_MAIL,
+    SyslogFacility::LOG_DAEMON, SyslogFacility::LOG_AUTH, SyslogFacility::LOG_SYSLOG,
+    SyslogFacility::LOG_LPR, SyslogFacility::LOG_NEWS, SyslogFacility::LOG_UUCP,
+    SyslogFacility::LOG_CRON, SyslogFacility::LOG_AUTHPRIV, SyslogFacility::LOG_FTP,
+    SyslogFacility::LOG_NTP, SyslogFacility::LOG_AUDIT, SyslogFacility::LOG_ALERT,
+    SyslogFacility::LOG_CLOCKD, SyslogFacility::LOG_LOCAL0, SyslogFacility::LOG_LOCAL1,
+    SyslogFacility::LOG_LOCAL2, SyslogFacility::LOG_LOCAL3, SyslogFacility::LOG_LOCAL4,
+    SyslogFacility::LOG_LOCAL5, SyslogFacility::LOG_LOCAL6, SyslogFacility::LOG_LOCAL7,
+];
+
 impl SyslogFacility {
     /// Convert an int (as used in the wire serialization) into a `SyslogFacility`
     pub fn from_int(i: i32) -> Option<Self> {
@@ -63,6 +78,22 @@ impl SyslogFacility {
         }
     }
 
+    /// Convert a `SyslogFacility` into its int (as used in the wire serialization); the inverse of
+    /// `from_int`
+    pub fn as_int(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Every facility, in order of their ints
+    pub fn all() -> &'static [SyslogFacility] {
+        &ALL_FACILITIES
+    }
+
+    /// The facility's lowercase name, as in JSON, without going through serde; the same as `as_str`
+    pub fn text(&self) -> &'static str {
+        self.as_str()
+    }
+
     /// Convert a syslog facility into a unique string representation
     pub fn as_str(&self) -> &'static str {
         match *self {
@@ -94,19 +125,93 @@ impl SyslogFacility {
     }
 }
 
+impl FromStr for SyslogFacility {
+    type Err = ();
+
+    /// Look up a facility by the name returned from `as_str` (which is also what it serializes as)
+    fn from_str(s: &str) -> Result<Self, ()> {
+        SyslogFacility::all().iter()
+            .find(|v| v.as_str() == s)
+            .cloned()
+            .ok_or(())
+    }
+}
+
 impl Serialize for SyslogFacility {
     fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
         ser.serialize_str(self.as_str())
     }
 }
 
+struct SyslogFacilityVisitor;
+
+impl<'de> Visitor<'de> for SyslogFacilityVisitor {
+    type Value = SyslogFacility;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a syslog facility name")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<SyslogFacility, E> {
+        v.parse().map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for SyslogFacility {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_str(SyslogFacilityVisitor)
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::SyslogFacility;
+    use serde_json;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_text() {
+        for v in SyslogFacility::all() {
+            assert_eq!(serde_json::to_string(v).unwrap(), format!("\"{}\"", v.text()));
+            assert_eq!(v.text(), v.as_str());
+        }
+    }
+
+    #[test]
+    fn test_as_int() {
+        for i in 0..24 {
+            assert_eq!(SyslogFacility::from_int(i).expect("valid facility").as_int(), i);
+        }
+        for i in 24..32 {
+            assert_eq!(SyslogFacility::from_int(i), None);
+        }
+    }
 
     #[test]
     fn test_deref() {
         assert_eq!(SyslogFacility::LOG_KERN.as_str(), "kern");
     }
+
+    #[test]
+    fn test_all() {
+        assert_eq!(SyslogFacility::all().len(), 24);
+        for (i, v) in SyslogFacility::all().iter().enumerate() {
+            assert_eq!(v.as_int(), i as i32);
+            assert_eq!(SyslogFacility::from_str(v.text()), Ok(*v));
+        }
+    }
+
+    #[test]
+    fn test_from_str() {
+        let names = ["kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp",
+                     "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clockd", "local0",
+                     "local1", "local2", "local3", "local4", "local5", "local6", "local7"];
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(SyslogFacility::from_str(name), Ok(SyslogFacility::from_int(i as i32).unwrap()));
+        }
+        assert_eq!("local0".parse(), Ok(SyslogFacility::LOG_LOCAL0));
+        assert_eq!(SyslogFacility::from_str("LOCAL0"), Err(()));
+        assert_eq!(SyslogFacility::from_str("local8"), Err(()));
+    }
 }
@@ -1,6 +1,7 @@
-//! Parser for [RFC 5424](https://tools.ietf.org/html/rfc5424) Syslog messages. Not to be confused
-//! with the older [RFC 3164](https://tools.ietf.org/html/rfc3164) BSD Syslog protocol, which many
-//! systems still emit.
+//! Parser for [RFC 3164](https://tools.ietf.org/html/rfc3164) BSD Syslog messages, which many
+//! systems still emit. Messages in the newer [RFC 5424](https://tools.ietf.org/html/rfc5424)
+//! format are recognized by the version number following the PRI and parsed with that grammar
+//! instead; their `version` is set accordingly, while RFC 3164 messages have a `version` of `0`.
 //!
 //! In particular, supports the Structured Data fields.
 //!
@@ -11,26 +12,40 @@
 //! A simple syslog server
 //!
 //! ```no_run
-//! use syslog_rfc3164::parse_message;
+//! use syslog_rfc3164::parse_message_bytes;
 //! use std::net::UdpSocket;
-//! use std::str;
 //!
 //! let s = UdpSocket::bind("127.0.0.1:10514").unwrap();
 //! let mut buf = [0u8; 2048];
 //! loop {
 //!     let (data_read, _) = s.recv_from(&mut buf).unwrap();
-//!     let msg = parse_message(str::from_utf8(&buf[0..data_read]).unwrap()).unwrap();
+//!     let msg = parse_message_bytes(&buf[0..data_read]).unwrap();
 //!     println!("{:?} {:?} {:?} {:?}", msg.facility, msg.severity, msg.hostname, msg.msg);
 //! }
 //! ```
 //!
-//! # Unimplemented Features
+//! # Non-UTF-8 Input
 //!
-//!  * Theoretically, you can send arbitrary (non-unicode) bytes for the message part of a syslog
-//!    message. Rust doesn't have a convenient way to only treat *some* of a buffer as utf-8,
-//!    so I'm just not supporting that. Most "real" syslog servers barf on it anway.
+//! Theoretically, you can send arbitrary (non-unicode) bytes for the message part of a syslog
+//! message. `parse_message_bytes` parses the (ASCII) header strictly and lossily converts the
+//! message body, so a stray Latin-1 byte doesn't cause the whole message to be rejected.
 //!
+//! # `no_std`
+//!
+//! The parser only needs `alloc`, so it builds without the default `std` feature. There is no
+//! clock to infer the year of RFC3164 timestamps from then, so messages whose timestamp doesn't
+//! include a year fail to parse with `MissingField("year")`, unless you pass the year in with
+//! `parse_message_ref_in_year`. `parse_message_with_reference` takes a `time::Tm`, so it is only
+//! available with `std`.
+//!
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
 #[cfg(test)]
+#[macro_use]
 extern crate assert_matches;
 extern crate log;
 #[cfg(test)]
@@ -38,15 +53,27 @@ extern crate pretty_env_logger;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(any(test, feature = "serde_json"))]
 extern crate serde_json;
+#[cfg(feature = "std")]
 extern crate time;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "smallvec")]
+extern crate smallvec;
 
 pub mod message;
 mod severity;
 mod facility;
+mod calendar;
 pub mod parser;
 
 pub use severity::SyslogSeverity;
 pub use facility::SyslogFacility;
 
-pub use parser::parse_message;
+pub use parser::{is_valid_message, parse_all, parse_concatenated, parse_framed, parse_into,
+                 parse_message, parse_message_bytes, parse_message_keep_raw, parse_message_opts,
+                 parse_message_owned, parse_message_partial, parse_message_ref,
+                 parse_message_ref_in_year, parse_multiline, ParseOptions, Rfc3164Decoder};
+#[cfg(feature = "std")]
+pub use parser::{parse_message_with_reference, parse_reader, ReadError};
@@ -1,18 +1,153 @@
 //! In-memory representation of a single Syslog message.
 
-use std::string::String;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::mem;
+use core::str::FromStr;
 
-use serde::{Serializer, Serialize};
+use log;
+use serde::{Deserialize, Deserializer, Serializer, Serialize};
+use serde::de::{self, Unexpected, Visitor};
+
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, TimeZone, Utc};
+#[cfg(feature = "serde_json")]
+use serde_json;
+#[cfg(feature = "smallvec")]
+use smallvec::SmallVec;
 
 #[allow(non_camel_case_types)]
 pub type time_t = i64;
 #[allow(non_camel_case_types)]
-pub type pid_t = i32;
+pub type pid_t = i64;
 
 use severity;
 use facility;
+use calendar::CalendarTime;
+use parser::{parse_message, parse_message_bytes, ParseError};
+
+#[derive(Copy,Clone,Debug)]
+/// A message's timestamp, in seconds since the epoch, along with whether its year had to be inferred
+///
+/// RFC3164 timestamps usually leave the year out, in which case the parser infers it from the
+/// current date (see `parser::parse_message_with_reference`). This serializes as just the seconds,
+/// so a deserialized `Timestamp` never has an inferred year. For the same reason, timestamps are
+/// compared and hashed by their seconds alone.
+pub struct Timestamp {
+    secs: time_t,
+    year_was_inferred: bool,
+}
+
+impl Timestamp {
+    /// A timestamp whose year was given explicitly
+    pub fn new(secs: time_t) -> Timestamp {
+        Timestamp { secs, year_was_inferred: false }
+    }
+
+    /// A timestamp whose year was inferred
+    pub fn with_inferred_year(secs: time_t) -> Timestamp {
+        Timestamp { secs, year_was_inferred: true }
+    }
+
+    /// Seconds since the epoch
+    pub fn secs(&self) -> time_t {
+        self.secs
+    }
+
+    /// Whether the message left out the year, and it was inferred instead
+    pub fn year_was_inferred(&self) -> bool {
+        self.year_was_inferred
+    }
+}
+
+impl PartialEq for Timestamp {
+    fn eq(&self, other: &Timestamp) -> bool {
+        self.secs == other.secs
+    }
+}
+
+impl Eq for Timestamp {}
 
-#[derive(Clone,Debug,PartialEq,Eq)]
+impl Hash for Timestamp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.secs.hash(state);
+    }
+}
+
+impl From<time_t> for Timestamp {
+    fn from(secs: time_t) -> Timestamp {
+        Timestamp::new(secs)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_i64(self.secs)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        time_t::deserialize(de).map(Timestamp::new)
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+/// A kernel ring buffer timestamp, as in the `[   12.345678]` that starts `dmesg`-style lines:
+/// the time since boot, rather than a wall-clock time
+///
+/// This is kept in nanoseconds, so that messages can still be compared and hashed, and serializes
+/// as (fractional) seconds.
+pub struct MonotonicTime {
+    nanos: u64,
+}
+
+impl MonotonicTime {
+    /// A time since boot in nanoseconds
+    pub fn from_nanos(nanos: u64) -> MonotonicTime {
+        MonotonicTime { nanos }
+    }
+
+    /// Nanoseconds since boot
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Seconds since boot, as in `12.345678`
+    pub fn as_secs_f64(&self) -> f64 {
+        self.nanos as f64 / 1e9
+    }
+}
+
+/// Renders as the kernel does, with the seconds padded to five places and six decimals, as in
+/// `[   12.345678]`
+impl fmt::Display for MonotonicTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:5}.{:06}]", self.nanos / 1_000_000_000, self.nanos % 1_000_000_000 / 1000)
+    }
+}
+
+impl Serialize for MonotonicTime {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_f64(self.as_secs_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for MonotonicTime {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        // Negative seconds saturate to zero
+        f64::deserialize(de).map(|secs| MonotonicTime::from_nanos((secs * 1e9 + 0.5) as u64))
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 /// `ProcID`s are usually numeric PIDs; however, on some systems, they may be something else
 pub enum ProcIdType {
     PID(pid_t),
@@ -20,52 +155,1414 @@ pub enum ProcIdType {
 }
 
 
+impl<'a> From<&'a str> for ProcIdType {
+    /// Numeric IDs become `PID`s; anything else is kept as a `Name`
+    fn from(s: &'a str) -> Self {
+        match pid_t::from_str(s) {
+            Ok(n) => ProcIdType::PID(n),
+            Err(_) => ProcIdType::Name(String::from(s)),
+        }
+    }
+}
+
+impl fmt::Display for ProcIdType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProcIdType::PID(ref p) => write!(f, "{}", p),
+            ProcIdType::Name(ref n) => f.write_str(n),
+        }
+    }
+}
+
 impl Serialize for ProcIdType {
     fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
         match *self {
-            ProcIdType::PID(ref p) => ser.serialize_i32(*p),
+            ProcIdType::PID(ref p) => ser.serialize_i64(*p),
             ProcIdType::Name(ref n) => ser.serialize_str(n),
         }
     }
 }
 
-#[derive(Clone,Debug,Serialize)]
+struct ProcIdTypeVisitor;
+
+impl<'de> Visitor<'de> for ProcIdTypeVisitor {
+    type Value = ProcIdType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer PID or a process name")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<ProcIdType, E> {
+        Ok(ProcIdType::PID(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<ProcIdType, E> {
+        if v > pid_t::MAX as u64 {
+            return Err(E::invalid_value(Unexpected::Unsigned(v), &self));
+        }
+        Ok(ProcIdType::PID(v as pid_t))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ProcIdType, E> {
+        Ok(ProcIdType::Name(String::from(v)))
+    }
+}
+
+/// Accepts either an integer (a `PID`) or a string (a `Name`), mirroring `Serialize`
+impl<'de> Deserialize<'de> for ProcIdType {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_any(ProcIdTypeVisitor)
+    }
+}
+
+/// The `(name, value)` params of a `StructuredElement`
+///
+/// With the `smallvec` feature, up to 4 params are stored inline rather than in a separate
+/// allocation. Use `StructuredElement::params` to read them the same way either way.
+#[cfg(not(feature = "smallvec"))]
+pub type SdParams = Vec<(String, String)>;
+
+/// The `(name, value)` params of a `StructuredElement`
+///
+/// With the `smallvec` feature, up to 4 params are stored inline rather than in a separate
+/// allocation. Use `StructuredElement::params` to read them the same way either way.
+#[cfg(feature = "smallvec")]
+pub type SdParams = SmallVec<[(String, String); 4]>;
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash,Serialize,Deserialize)]
+/// A single `[id key="value" ...]` block of RFC5424 Structured Data
+pub struct StructuredElement {
+    pub id: String,
+    pub params: SdParams,
+}
+
+impl StructuredElement {
+    /// The element's `(name, value)` params, in order, however they are stored
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+}
+
+impl fmt::Display for StructuredElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}", self.id)?;
+        for (name, value) in &self.params {
+            write!(f, " {}=\"", name)?;
+            for chr in value.chars() {
+                if chr == '"' || chr == '\\' || chr == ']' {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{}", chr)?;
+            }
+            f.write_str("\"")?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+/// Which syslog grammar a message was parsed with
+pub enum Dialect {
+    /// The BSD syslog format, which has no version number
+    Rfc3164,
+    /// The IETF syslog format, identified by the version number following the PRI
+    Rfc5424,
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash,Serialize,Deserialize)]
 pub struct SyslogMessage {
     pub severity: severity::SyslogSeverity,
     pub facility: facility::SyslogFacility,
+    /// The raw PRI value, as it appeared on the wire, or `parser::MISSING_PRIORITY` if there was
+    /// none
+    pub priority: i32,
+    /// Whether the PRI couldn't be decoded, and the facility and severity are the defaults from
+    /// `ParseOptions` instead
+    #[serde(default)]
+    pub pri_recovered: bool,
+    /// The sequence number some senders (notably Cisco devices) put before the PRI, as in
+    /// `123: <189>...`
+    pub seq_no: Option<u64>,
     pub version: i32,
-    pub timestamp: Option<time_t>,
+    pub timestamp: Option<Timestamp>,
+    /// Fractional seconds of the timestamp, in nanoseconds, if the message included them
+    pub timestamp_nanos: Option<u32>,
+    /// The kernel ring buffer timestamp of a `dmesg`-style line, as in
+    /// `<4>[   12.345678] some kernel message`, which has no wall-clock `timestamp`, or of a
+    /// relayed kernel message, as in `host kernel: [   12.345678] some kernel message`, where it
+    /// starts the body of a message tagged `kernel`. Either way, it isn't part of `msg`.
+    #[serde(default)]
+    pub monotonic: Option<MonotonicTime>,
     pub hostname: Option<String>,
+    /// The port following the hostname, as in `host1:514`, which some relays add
+    pub port: Option<u16>,
     pub proc_id: Option<ProcIdType>,
     pub tag: Option<String>,
+    /// The RFC5424 MSGID, identifying the type of message; always `None` for RFC3164 messages
+    pub msg_id: Option<String>,
+    pub structured_data: Vec<StructuredElement>,
     pub msg: String,
+    /// Whether the message's structure was parsed; `false` only if parsing failed and
+    /// `ParseOptions::fallback_raw_msg` put the whole input into `msg` instead
+    #[serde(default = "default_true")]
+    pub parsed_structure: bool,
+    /// The exact input the message was parsed from (with any invalid UTF-8 replaced by U+FFFD),
+    /// if `ParseOptions::keep_raw` was set; left out of the serialized message when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Serialize)]
+/// A view of a `SyslogMessage` that serializes more compactly, as returned by
+/// `SyslogMessage::compact`, e.g. for high-volume forwarding
+///
+/// Fields that are `None` (or empty, or `false`) are left out rather than written as `null`, and
+/// the field names are shortened:
+///
+/// | Field              | Name        |
+/// |--------------------|-------------|
+/// | `severity`         | `sev`       |
+/// | `facility`         | `fac`       |
+/// | `priority`         | `pri`       |
+/// | `pri_recovered`    | `recovered` |
+/// | `seq_no`           | `seq`       |
+/// | `version`          | `ver`       |
+/// | `timestamp`        | `ts`        |
+/// | `timestamp_nanos`  | `ts_nanos`  |
+/// | `monotonic`        | `mono`      |
+/// | `hostname`         | `host`      |
+/// | `proc_id`          | `pid`       |
+/// | `msg_id`           | `msgid`     |
+/// | `structured_data`  | `sd`        |
+/// | `parsed_structure` | `parsed`    |
+///
+/// `parsed_structure` is left out when it's `true` rather than `false`. The rest keep their
+/// names. Serializing a `SyslogMessage` itself still writes every field.
+pub struct CompactSyslogMessage<'a> {
+    #[serde(rename = "sev")]
+    pub severity: severity::SyslogSeverity,
+    #[serde(rename = "fac")]
+    pub facility: facility::SyslogFacility,
+    #[serde(rename = "pri")]
+    pub priority: i32,
+    #[serde(rename = "recovered", skip_serializing_if = "is_false")]
+    pub pri_recovered: bool,
+    #[serde(rename = "seq", skip_serializing_if = "Option::is_none")]
+    pub seq_no: Option<u64>,
+    #[serde(rename = "ver")]
+    pub version: i32,
+    #[serde(rename = "ts", skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    #[serde(rename = "ts_nanos", skip_serializing_if = "Option::is_none")]
+    pub timestamp_nanos: Option<u32>,
+    #[serde(rename = "mono", skip_serializing_if = "Option::is_none")]
+    pub monotonic: Option<MonotonicTime>,
+    #[serde(rename = "host", skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(rename = "pid", skip_serializing_if = "Option::is_none")]
+    pub proc_id: Option<&'a ProcIdType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<&'a str>,
+    #[serde(rename = "msgid", skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<&'a str>,
+    #[serde(rename = "sd", skip_serializing_if = "is_empty")]
+    pub structured_data: &'a [StructuredElement],
+    pub msg: &'a str,
+    #[serde(rename = "parsed", skip_serializing_if = "is_true")]
+    pub parsed_structure: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<&'a str>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_empty(elements: &&[StructuredElement]) -> bool {
+    elements.is_empty()
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+/// A `SyslogMessage` whose string fields borrow from the parsed input instead of owning copies,
+/// as returned by `parse_message_ref`
+pub struct SyslogMessageRef<'a> {
+    pub severity: severity::SyslogSeverity,
+    pub facility: facility::SyslogFacility,
+    /// The raw PRI value, as it appeared on the wire, or `parser::MISSING_PRIORITY` if there was
+    /// none
+    pub priority: i32,
+    /// Whether the PRI couldn't be decoded, and the facility and severity are the defaults from
+    /// `ParseOptions` instead
+    pub pri_recovered: bool,
+    /// The sequence number some senders (notably Cisco devices) put before the PRI, as in
+    /// `123: <189>...`
+    pub seq_no: Option<u64>,
+    pub version: i32,
+    pub timestamp: Option<Timestamp>,
+    /// Fractional seconds of the timestamp, in nanoseconds, if the message included them
+    pub timestamp_nanos: Option<u32>,
+    /// The kernel ring buffer timestamp of a `dmesg`-style line, as in
+    /// `<4>[   12.345678] some kernel message`, which has no wall-clock `timestamp`, or of a
+    /// relayed kernel message, as in `host kernel: [   12.345678] some kernel message`, where it
+    /// starts the body of a message tagged `kernel`. Either way, it isn't part of `msg`.
+    pub monotonic: Option<MonotonicTime>,
+    pub hostname: Option<&'a str>,
+    /// The port following the hostname, as in `host1:514`, which some relays add
+    pub port: Option<u16>,
+    /// The process ID exactly as it appeared in the message; see `ProcIdType::from`
+    pub proc_id: Option<&'a str>,
+    pub tag: Option<&'a str>,
+    /// The RFC5424 MSGID, identifying the type of message; always `None` for RFC3164 messages
+    pub msg_id: Option<&'a str>,
+    pub structured_data: Vec<StructuredElement>,
+    pub msg: &'a str,
+    /// Whether the message's structure was parsed; `false` only if parsing failed and
+    /// `ParseOptions::fallback_raw_msg` put the whole input into `msg` instead
+    pub parsed_structure: bool,
+}
+
+impl<'a> SyslogMessageRef<'a> {
+    /// Copy the borrowed fields into a standalone `SyslogMessage`
+    pub fn into_owned(self) -> SyslogMessage {
+        self.into_buffers(None, None, None, String::new())
+    }
+
+    /// Copy the borrowed fields into `out`, reusing its `String` buffers
+    ///
+    /// `out`'s `hostname`, `tag`, `msg_id` and `msg` keep their capacity if they are `Some` (or,
+    /// for `msg`, always); the other fields are overwritten, and `raw` is cleared.
+    pub fn write_into(self, out: &mut SyslogMessage) {
+        let msg = mem::take(&mut out.msg);
+        *out = self.into_buffers(out.hostname.take(), out.tag.take(), out.msg_id.take(), msg);
+    }
+
+    fn into_buffers(
+        self,
+        hostname: Option<String>,
+        tag: Option<String>,
+        msg_id: Option<String>,
+        msg: String,
+    ) -> SyslogMessage {
+        SyslogMessage {
+            severity: self.severity,
+            facility: self.facility,
+            priority: self.priority,
+            pri_recovered: self.pri_recovered,
+            seq_no: self.seq_no,
+            version: self.version,
+            timestamp: self.timestamp,
+            timestamp_nanos: self.timestamp_nanos,
+            monotonic: self.monotonic,
+            hostname: self.hostname.map(|s| copy_into(hostname.unwrap_or_default(), s)),
+            port: self.port,
+            proc_id: self.proc_id.map(ProcIdType::from),
+            tag: self.tag.map(|s| copy_into(tag.unwrap_or_default(), s)),
+            msg_id: self.msg_id.map(|s| copy_into(msg_id.unwrap_or_default(), s)),
+            structured_data: self.structured_data,
+            msg: copy_into(msg, self.msg),
+            parsed_structure: self.parsed_structure,
+            raw: None,
+        }
+    }
+}
+
+// Replace `buf`'s contents with `s`, keeping its capacity
+fn copy_into(mut buf: String, s: &str) -> String {
+    buf.clear();
+    buf.push_str(s);
+    buf
+}
+
+/// Compares every field but `raw`, which a `SyslogMessageRef` doesn't have, without copying the
+/// borrowed message. `proc_id`s are equal if `ProcIdType::from` would make them so.
+impl<'a> PartialEq<SyslogMessageRef<'a>> for SyslogMessage {
+    fn eq(&self, other: &SyslogMessageRef<'a>) -> bool {
+        let proc_ids_eq = match (&self.proc_id, other.proc_id) {
+            (&Some(ProcIdType::PID(pid)), Some(s)) => pid_t::from_str(s) == Ok(pid),
+            (&Some(ProcIdType::Name(ref name)), Some(s)) => name == s && pid_t::from_str(s).is_err(),
+            (&None, None) => true,
+            _ => false,
+        };
+        proc_ids_eq
+            && self.severity == other.severity
+            && self.facility == other.facility
+            && self.priority == other.priority
+            && self.pri_recovered == other.pri_recovered
+            && self.seq_no == other.seq_no
+            && self.version == other.version
+            && self.timestamp == other.timestamp
+            && self.timestamp_nanos == other.timestamp_nanos
+            && self.monotonic == other.monotonic
+            && self.hostname.as_deref() == other.hostname
+            && self.port == other.port
+            && self.tag.as_deref() == other.tag
+            && self.msg_id.as_deref() == other.msg_id
+            && self.structured_data == other.structured_data
+            && self.msg == other.msg
+            && self.parsed_structure == other.parsed_structure
+    }
+}
+
+/// The same comparison as `SyslogMessage == SyslogMessageRef`
+impl<'a> PartialEq<SyslogMessage> for SyslogMessageRef<'a> {
+    fn eq(&self, other: &SyslogMessage) -> bool {
+        other == self
+    }
+}
+
+impl SyslogMessage {
+    /// A view of the message that serializes without `null` fields and with shorter field names;
+    /// see `CompactSyslogMessage`
+    pub fn compact<'a>(&'a self) -> CompactSyslogMessage<'a> {
+        CompactSyslogMessage {
+            severity: self.severity,
+            facility: self.facility,
+            priority: self.priority,
+            pri_recovered: self.pri_recovered,
+            seq_no: self.seq_no,
+            version: self.version,
+            timestamp: self.timestamp,
+            timestamp_nanos: self.timestamp_nanos,
+            monotonic: self.monotonic,
+            hostname: self.hostname.as_deref(),
+            port: self.port,
+            proc_id: self.proc_id.as_ref(),
+            tag: self.tag.as_deref(),
+            msg_id: self.msg_id.as_deref(),
+            structured_data: &self.structured_data,
+            msg: &self.msg,
+            parsed_structure: self.parsed_structure,
+            raw: self.raw.as_deref(),
+        }
+    }
+
+    /// Which syslog grammar the message was parsed with, according to its `version`
+    pub fn dialect(&self) -> Dialect {
+        if self.version == 0 {
+            Dialect::Rfc3164
+        } else {
+            Dialect::Rfc5424
+        }
+    }
+
+    /// The numeric code of the message's severity
+    pub fn severity_code(&self) -> i32 {
+        self.severity.as_int()
+    }
+
+    /// The `log` crate level matching the message's severity, for re-logging it; see
+    /// `SyslogSeverity::to_log_level`
+    pub fn to_log_level(&self) -> log::Level {
+        self.severity.to_log_level()
+    }
+
+    /// The numeric code of the message's facility
+    pub fn facility_code(&self) -> i32 {
+        self.facility.as_int()
+    }
+
+    /// The facility and severity names joined by a dot, as in `local0.info`; the same labels that
+    /// syslog.conf selectors use
+    pub fn pri_label(&self) -> String {
+        format!("{}.{}", self.facility.as_str(), self.severity.as_str())
+    }
+
+    /// Flatten the message into a map with a fixed set of keys, for shipping to structured logging
+    /// backends
+    ///
+    /// Severity and facility are rendered by name and the timestamp in RFC3339 format. `None`
+    /// fields (and empty structured data) are omitted.
+    pub fn to_fields(&self) -> BTreeMap<&'static str, String> {
+        let mut fields = BTreeMap::new();
+        fields.insert("severity", String::from(self.severity.as_str()));
+        fields.insert("facility", String::from(self.facility.as_str()));
+        fields.insert("priority", self.priority.to_string());
+        fields.insert("version", self.version.to_string());
+        if let Some(seq_no) = self.seq_no {
+            fields.insert("seq_no", seq_no.to_string());
+        }
+        if let Some(ct) = self.timestamp.and_then(|ts| CalendarTime::from_epoch(ts.secs())) {
+            fields.insert("timestamp", ct.to_rfc3339(self.timestamp_nanos));
+        }
+        if let Some(ref hostname) = self.hostname {
+            fields.insert("hostname", hostname.clone());
+        }
+        if let Some(port) = self.port {
+            fields.insert("port", port.to_string());
+        }
+        if let Some(ref proc_id) = self.proc_id {
+            fields.insert("proc_id", proc_id.to_string());
+        }
+        if let Some(ref tag) = self.tag {
+            fields.insert("tag", tag.clone());
+        }
+        if let Some(ref msg_id) = self.msg_id {
+            fields.insert("msg_id", msg_id.clone());
+        }
+        if !self.structured_data.is_empty() {
+            let sd: Vec<String> = self.structured_data.iter().map(|e| e.to_string()).collect();
+            fields.insert("structured_data", sd.concat());
+        }
+        fields.insert("msg", self.msg.clone());
+        fields
+    }
+
+    /// The message text, without a leading `tag`, `tag:` or `tag[pid]:` that some senders repeat in
+    /// it
+    ///
+    /// Only the message's own `tag` is stripped, along with any whitespace following it; if the
+    /// message doesn't have a tag, or the text doesn't start with it, this is just `msg`.
+    pub fn message_body(&self) -> &str {
+        let tag = match self.tag {
+            Some(ref tag) if self.msg.starts_with(tag.as_str()) => tag,
+            _ => return &self.msg,
+        };
+        let mut rest = &self.msg[tag.len()..];
+        if rest.starts_with('[') {
+            match rest.find(']') {
+                Some(end) => rest = &rest[end + 1..],
+                None => return &self.msg,
+            }
+        }
+        if rest.starts_with(':') {
+            rest = &rest[1..];
+        } else if !rest.is_empty() && !rest.starts_with(' ') {
+            // Just a word that happens to start with the tag, as in `su` and `success`
+            return &self.msg;
+        }
+        rest.trim_start()
+    }
+
+    /// The `key=value` pairs in `msg`, as many devices (Cisco's among them) log, in the order they
+    /// appear
+    ///
+    /// Pairs are separated by whitespace. A value may be double-quoted to include whitespace, as in
+    /// `user="John Smith"`, in which case `\"` and `\\` are unescaped; otherwise it runs to the
+    /// next whitespace, and may be empty. Words without an `=` are skipped. This is separate from
+    /// `structured_data`, and `msg` isn't changed.
+    pub fn parse_kv(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        let mut rest = self.msg.trim_start();
+        while !rest.is_empty() {
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let key_end = match rest[..word_end].find('=') {
+                Some(idx) if idx > 0 => idx,
+                _ => {
+                    rest = rest[word_end..].trim_start();
+                    continue;
+                }
+            };
+            let key = &rest[..key_end];
+            rest = &rest[key_end + 1..];
+            let value = if rest.starts_with('"') {
+                let mut value = String::new();
+                let mut chars = rest[1..].char_indices();
+                let mut end = rest.len();
+                while let Some((idx, chr)) = chars.next() {
+                    match chr {
+                        '"' => {
+                            end = idx + 2;
+                            break;
+                        }
+                        '\\' => match chars.next() {
+                            Some((_, next)) if next == '"' || next == '\\' => value.push(next),
+                            Some((_, next)) => {
+                                value.push('\\');
+                                value.push(next);
+                            }
+                            None => value.push('\\'),
+                        },
+                        _ => value.push(chr),
+                    }
+                }
+                rest = &rest[end..];
+                value
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = String::from(&rest[..end]);
+                rest = &rest[end..];
+                value
+            };
+            pairs.push((String::from(key), value));
+            rest = rest.trim_start();
+        }
+        pairs
+    }
+
+    /// `msg` with every word matching one of the glob `patterns` replaced by `***`, e.g. to keep
+    /// email addresses (`*@*`) or IPv4 addresses (`*.*.*.*`) out of storage
+    ///
+    /// In a pattern, `*` matches any run of characters (including none) and `?` any one character;
+    /// everything else matches itself, case-sensitively. Patterns are matched against whole words,
+    /// which are separated by whitespace, quotes, brackets, `,`, `;` and `=`, so the value of
+    /// `src=10.0.0.1` or `<jdoe@example.com>` is a word of its own. A trailing `.`, `:`, `!` or `?`
+    /// isn't part of a word either. `msg` is only copied if something matches.
+    pub fn redacted_msg<'a>(&'a self, patterns: &[&str]) -> Cow<'a, str> {
+        let mut redacted = String::new();
+        let mut copied = 0;
+        let words = self.msg
+            .split(|c: char| c.is_whitespace() || "\"'()<>[]{},;=".contains(c))
+            .map(|word| word.trim_end_matches(|c| ".:!?".contains(c)))
+            .filter(|word| !word.is_empty());
+        for word in words {
+            if patterns.iter().any(|pattern| glob_match(pattern, word)) {
+                let start = word.as_ptr() as usize - self.msg.as_ptr() as usize;
+                redacted.push_str(&self.msg[copied..start]);
+                redacted.push_str("***");
+                copied = start + word.len();
+            }
+        }
+        if redacted.is_empty() {
+            return Cow::Borrowed(&self.msg);
+        }
+        redacted.push_str(&self.msg[copied..]);
+        Cow::Owned(redacted)
+    }
+
+    /// If this is a `last message repeated N times` summary, as syslog daemons write in place of
+    /// repeats of the previous message, how many times it was repeated
+    ///
+    /// Such a message usually has no tag, so the parser takes `last` for one; that is recognized
+    /// too. Any other message gives `None`.
+    pub fn repeated_count(&self) -> Option<u32> {
+        let text = match self.tag {
+            Some(ref tag) if tag == "last" => self.msg.strip_prefix("message repeated ")?,
+            _ => self.message_body().strip_prefix("last message repeated ")?,
+        };
+        let text = text.trim_end();
+        let count = text.strip_suffix(" times").or_else(|| text.strip_suffix(" time"))?;
+        count.parse().ok()
+    }
+
+    /// Render the message as with `Display`, prefixed with its length in bytes, as in the
+    /// octet-counted framing of [RFC 6587](https://tools.ietf.org/html/rfc6587#section-3.4.1)
+    ///
+    /// This is the inverse of `parser::parse_framed`, for forwarding messages over TCP.
+    pub fn to_framed(&self) -> String {
+        let line = self.to_string();
+        format!("{} {}", line.len(), line)
+    }
+
+    /// Render the message in the syntax of RFC5424, as in
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    ///
+    /// This lets relays upgrade RFC3164 messages. The timestamp is written in RFC3339 form, in UTC,
+    /// with fractional seconds cut down to the microseconds RFC5424 allows. The tag becomes the
+    /// APP-NAME, and missing fields are written as the NILVALUE `-`. Header fields are cut down to
+    /// RFC5424's limits (255 bytes for the HOSTNAME, 48 for the APP-NAME, 128 for the PROCID and
+    /// 32 for the MSGID), and characters they can't contain, like spaces, are replaced by `_`.
+    /// RFC5424 has no place for a `port` or `seq_no`, so they're dropped.
+    pub fn to_rfc5424(&self) -> String {
+        fn header_field(field: Option<&str>, max_len: usize) -> String {
+            match field {
+                Some(f) if !f.is_empty() => {
+                    f.chars().map(|c| if c > ' ' && c <= '~' { c } else { '_' }).take(max_len).collect()
+                }
+                _ => String::from("-"),
+            }
+        }
+
+        let pri = self.facility_code() << 3 | self.severity_code();
+        let timestamp = match self.timestamp.and_then(|ts| CalendarTime::from_epoch(ts.secs())) {
+            Some(ct) => {
+                let mut timestamp = ct.to_rfc3339(None);
+                if let Some(nanos) = self.timestamp_nanos {
+                    timestamp.pop();
+                    timestamp.push_str(&format!(".{:06}Z", nanos / 1000));
+                }
+                timestamp
+            }
+            None => String::from("-"),
+        };
+        let proc_id = self.proc_id.as_ref().map(|p| p.to_string());
+        let mut s = format!("<{}>1 {} {} {} {} {} ", pri, timestamp,
+                            header_field(self.hostname.as_deref(), 255),
+                            header_field(self.tag.as_deref(), 48),
+                            header_field(proc_id.as_deref(), 128),
+                            header_field(self.msg_id.as_deref(), 32));
+        if self.structured_data.is_empty() {
+            s.push('-');
+        }
+        for element in &self.structured_data {
+            s.push_str(&element.to_string());
+        }
+        if !self.msg.is_empty() {
+            s.push(' ');
+            s.push_str(&self.msg);
+        }
+        s
+    }
+
+    /// Compare two messages as `==` does, except that inferred timestamp years are ignored
+    ///
+    /// RFC3164 timestamps usually don't include a year, so the parser infers one from the current
+    /// date, and parsing the same message in different years gives different `timestamp`s. This
+    /// compares `severity`, `facility`, `priority`, `pri_recovered`, `seq_no`, `version`,
+    /// `timestamp_nanos`, `monotonic`, `hostname`, `port`, `proc_id`, `tag`, `msg_id`,
+    /// `structured_data`, `msg` and `parsed_structure` exactly, and ignores `raw`.
+    /// `timestamp`s are compared by month, day, hour, minute and second (in UTC) if either one's
+    /// year was inferred, and by their seconds otherwise.
+    pub fn eq_ignoring_inferred_year(&self, other: &Self) -> bool {
+        let timestamps_eq = match (self.timestamp, other.timestamp) {
+            (Some(a), Some(b)) if a.year_was_inferred() || b.year_was_inferred() => {
+                match (CalendarTime::from_epoch(a.secs()), CalendarTime::from_epoch(b.secs())) {
+                    (Some(a), Some(b)) => CalendarTime { year: b.year, ..a } == b,
+                    _ => a.secs() == b.secs(),
+                }
+            }
+            (a, b) => a.map(|ts| ts.secs()) == b.map(|ts| ts.secs()),
+        };
+        timestamps_eq
+            && self.severity == other.severity
+            && self.facility == other.facility
+            && self.priority == other.priority
+            && self.pri_recovered == other.pri_recovered
+            && self.seq_no == other.seq_no
+            && self.version == other.version
+            && self.timestamp_nanos == other.timestamp_nanos
+            && self.monotonic == other.monotonic
+            && self.hostname == other.hostname
+            && self.port == other.port
+            && self.proc_id == other.proc_id
+            && self.tag == other.tag
+            && self.msg_id == other.msg_id
+            && self.structured_data == other.structured_data
+            && self.msg == other.msg
+            && self.parsed_structure == other.parsed_structure
+    }
+
+    /// How many seconds before `now` (in seconds since the epoch) the message was timestamped, or
+    /// `None` if it doesn't have a timestamp
+    ///
+    /// Fractional seconds are ignored. The age is negative for a timestamp after `now`, which can
+    /// also happen when the year had to be inferred and was guessed wrong, e.g. because the
+    /// sender's clock is off. If that matters, check `Timestamp::year_was_inferred` first.
+    pub fn age_seconds(&self, now: time_t) -> Option<i64> {
+        self.timestamp.map(|ts| now - ts.secs())
+    }
+
+    /// Order two messages by their timestamps, including fractional seconds, e.g. for
+    /// `sort_by` or merging sorted logs
+    ///
+    /// A message without a timestamp comes before every message with one. Nothing else is
+    /// compared, so messages with the same timestamp are `Equal` (and a stable sort keeps them in
+    /// their original order).
+    pub fn cmp_by_time(&self, other: &Self) -> Ordering {
+        let key = |m: &Self| m.timestamp.map(|ts| (ts.secs(), m.timestamp_nanos.unwrap_or(0)));
+        key(self).cmp(&key(other))
+    }
+
+    /// The timestamp as a `chrono::DateTime<Utc>`, or `None` if the message didn't have one
+    ///
+    /// Only available with the `chrono` feature
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_datetime(&self) -> Option<DateTime<Utc>> {
+        self.timestamp.and_then(|ts| Utc.timestamp_opt(ts.secs(), self.timestamp_nanos.unwrap_or(0)).single())
+    }
+
+    /// The same JSON as `serde_json::Value::from`, except that the timestamp is an RFC3339 string,
+    /// as in `"2017-01-08T12:14:16.500000000Z"`, rather than seconds since the epoch
+    ///
+    /// Serializing the message still writes the integer timestamp. Only available with the
+    /// `serde_json` feature
+    #[cfg(feature = "serde_json")]
+    pub fn to_json_rfc3339(&self) -> serde_json::Value {
+        let mut value = serde_json::Value::from(self);
+        let timestamp = self.timestamp
+            .and_then(|ts| CalendarTime::from_epoch(ts.secs()))
+            .map(|ct| ct.to_rfc3339(self.timestamp_nanos));
+        if let serde_json::Value::Object(ref mut map) = value {
+            map.insert(String::from("timestamp"), serde_json::Value::from(timestamp));
+        }
+        value
+    }
+}
+
+// Whether all of `s` matches the glob `pattern`; see `SyslogMessage::redacted_msg`. On a mismatch
+// this only backtracks to the last `*`, letting it match one more char, so it takes at most
+// `pattern.len() * s.len()` steps.
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut p, mut i) = (0, 0);
+    // The index of the last `*` in `pattern`, and of the char in `s` it's matched up to
+    let mut star = None;
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            star = Some((star_p, star_i + 1));
+            p = star_p + 1;
+            i = star_i + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Renders the message back into the RFC3164 wire format understood by `parse_message`, with
+/// `None` fields written as `-`
+impl fmt::Display for SyslogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seq_no) = self.seq_no {
+            write!(f, "{}: ", seq_no)?;
+        }
+        let pri = self.facility_code() << 3 | self.severity_code();
+        write!(f, "<{}>", pri)?;
+        // A `dmesg`-style line has nothing but the time since boot between the PRI and the body
+        let header_is_empty = self.timestamp.is_none() && self.hostname.is_none() && self.tag.is_none()
+            && self.proc_id.is_none() && self.structured_data.is_empty();
+        if let Some(monotonic) = self.monotonic.filter(|_| header_is_empty) {
+            write!(f, "{} ", monotonic)?;
+            return f.write_str(&self.msg);
+        }
+        match self.timestamp.and_then(|ts| CalendarTime::from_epoch(ts.secs())) {
+            Some(ct) => {
+                // Days 1-9 are padded with a space rather than a zero, as in `Jan  8`
+                write!(f, "{} {:2} {:02}:{:02}:{:02}",
+                       MONTHS[ct.month as usize - 1], ct.day, ct.hour, ct.minute, ct.second)?;
+                if let Some(nanos) = self.timestamp_nanos {
+                    write!(f, ".{:09}", nanos)?;
+                }
+            }
+            None => f.write_str("-")?,
+        }
+        match (self.hostname.as_ref(), self.port) {
+            // An IPv6 address needs brackets to keep its colons apart from the port's
+            (Some(h), Some(port)) if h.contains(':') => write!(f, " [{}]:{}", h, port)?,
+            (Some(h), Some(port)) => write!(f, " {}:{}", h, port)?,
+            (Some(h), None) => write!(f, " {}", h)?,
+            (None, _) => f.write_str(" -")?,
+        }
+        // `tag[pid]:`, with `-` standing in for a missing tag
+        match (&self.tag, &self.proc_id) {
+            (&None, &None) => f.write_str(" - -")?,
+            (tag, proc_id) => {
+                f.write_str(" ")?;
+                match *tag {
+                    Some(ref t) => f.write_str(t)?,
+                    None => f.write_str("-")?,
+                }
+                if let Some(ref p) = *proc_id {
+                    write!(f, "[{}]", p)?;
+                }
+                f.write_str(":")?;
+            }
+        }
+        f.write_str(" ")?;
+        if !self.structured_data.is_empty() {
+            for element in &self.structured_data {
+                write!(f, "{}", element)?;
+            }
+            f.write_str(" ")?;
+        }
+        if let Some(monotonic) = self.monotonic {
+            write!(f, "{} ", monotonic)?;
+        }
+        f.write_str(&self.msg)
+    }
+}
+
+/// Builds the same JSON that serializing the message produces, field by field
+///
+/// Only available with the `serde_json` feature
+#[cfg(feature = "serde_json")]
+impl<'a> From<&'a SyslogMessage> for serde_json::Value {
+    fn from(m: &'a SyslogMessage) -> serde_json::Value {
+        use serde_json::{Map, Value};
+
+        let proc_id = match m.proc_id {
+            Some(ProcIdType::PID(p)) => Value::from(p),
+            Some(ProcIdType::Name(ref n)) => Value::from(n.as_str()),
+            None => Value::Null,
+        };
+        let structured_data = m.structured_data.iter().map(|e| {
+            let params = e.params.iter()
+                .map(|(name, value)| Value::from(vec![name.as_str(), value.as_str()]))
+                .collect::<Vec<Value>>();
+            let mut element = Map::new();
+            element.insert(String::from("id"), Value::from(e.id.as_str()));
+            element.insert(String::from("params"), Value::from(params));
+            Value::Object(element)
+        }).collect::<Vec<Value>>();
+
+        let mut map = Map::new();
+        map.insert(String::from("severity"), Value::from(m.severity.as_str()));
+        map.insert(String::from("facility"), Value::from(m.facility.as_str()));
+        map.insert(String::from("priority"), Value::from(m.priority));
+        map.insert(String::from("pri_recovered"), Value::from(m.pri_recovered));
+        map.insert(String::from("seq_no"), Value::from(m.seq_no));
+        map.insert(String::from("version"), Value::from(m.version));
+        map.insert(String::from("timestamp"), Value::from(m.timestamp.map(|ts| ts.secs())));
+        map.insert(String::from("timestamp_nanos"), Value::from(m.timestamp_nanos));
+        map.insert(String::from("monotonic"), Value::from(m.monotonic.map(|t| t.as_secs_f64())));
+        map.insert(String::from("hostname"), Value::from(m.hostname.clone()));
+        map.insert(String::from("port"), Value::from(m.port));
+        map.insert(String::from("proc_id"), proc_id);
+        map.insert(String::from("tag"), Value::from(m.tag.clone()));
+        map.insert(String::from("msg_id"), Value::from(m.msg_id.clone()));
+        map.insert(String::from("structured_data"), Value::from(structured_data));
+        map.insert(String::from("msg"), Value::from(m.msg.as_str()));
+        map.insert(String::from("parsed_structure"), Value::from(m.parsed_structure));
+        if let Some(ref raw) = m.raw {
+            map.insert(String::from("raw"), Value::from(raw.as_str()));
+        }
+        Value::Object(map)
+    }
+}
+
+impl FromStr for SyslogMessage {
+    type Err = ParseError;
+
+    /// Parse a string into a `SyslogMessage`
+    ///
+    /// Same as calling `parse_message` on the string
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_message(s)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for SyslogMessage {
+    type Error = ParseError;
+
+    /// Parse a byte buffer, such as a UDP datagram, into a `SyslogMessage`
+    ///
+    /// Same as calling `parse_message_bytes` on the buffer
+    fn try_from(b: &'a [u8]) -> Result<Self, Self::Error> {
+        parse_message_bytes(b)
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
     use serde_json;
-    use super::SyslogMessage;
+    use super::{Dialect, ProcIdType, StructuredElement, SyslogMessage, Timestamp};
+    use parser::{parse_framed, parse_message, parse_message_ref, parse_message_with_reference};
     use severity::SyslogSeverity::*;
     use facility::SyslogFacility::*;
+    use time;
 
     #[test]
     fn test_serialization_serde() {
         let m = SyslogMessage {
             severity: SEV_INFO,
             facility: LOG_KERN,
+            priority: 6,
+            pri_recovered: false,
+            seq_no: None,
             version: 1,
             timestamp: None,
+            timestamp_nanos: None,
+            monotonic: None,
             hostname: None,
+            port: None,
             proc_id: None,
             tag: None,
-            msg: String::from("")
+            msg_id: None,
+            structured_data: vec![],
+            msg: String::from(""),
+            parsed_structure: true,
+            raw: None,
         };
 
         let encoded = serde_json::to_string(&m).expect("Should encode to JSON");
 //        println!("{:?}", encoded);
         // XXX: we don't have a guaranteed order, I don't think, so this might break with minor
         // version changes. *shrug*
-        assert_eq!(encoded, "{\"severity\":\"info\",\"facility\":\"kern\",\"version\":1,\"timestamp\":null,\"hostname\":null,\"proc_id\":null,\"tag\":null,\"msg\":\"\"}");
+        assert_eq!(encoded, "{\"severity\":\"info\",\"facility\":\"kern\",\"priority\":6,\"pri_recovered\":false,\"seq_no\":null,\"version\":1,\"timestamp\":null,\"timestamp_nanos\":null,\"monotonic\":null,\"hostname\":null,\"port\":null,\"proc_id\":null,\"tag\":null,\"msg_id\":null,\"structured_data\":[],\"msg\":\"\",\"parsed_structure\":true}");
+
+        let encoded = serde_json::to_string(&m.compact()).expect("Should encode to JSON");
+        assert_eq!(encoded, "{\"sev\":\"info\",\"fac\":\"kern\",\"pri\":6,\"ver\":1,\"msg\":\"\"}");
+    }
+
+    #[test]
+    fn test_from_str() {
+        let m: SyslogMessage = "<13>Feb 18 20:53:31 host app: hi".parse().expect("Should parse");
+        assert_eq!(m.severity, SEV_NOTICE);
+        assert_eq!(m.facility, LOG_USER);
+        assert_eq!(m.hostname, Some(String::from("host")));
+    }
+
+    #[test]
+    fn test_dialect() {
+        let m = parse_message("<78>Jan 8 12:14:16 host1 123 CROND some_message").expect("Should parse");
+        assert_eq!(m.dialect(), Dialect::Rfc3164);
+
+        let m = parse_message("<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - msg")
+            .expect("Should parse");
+        assert_eq!(m.dialect(), Dialect::Rfc5424);
+    }
+
+    #[test]
+    fn test_hash() {
+        let input = "<78>Mar 15 14:16:22 host1 CROND[10391]: - [meta sequenceId=\"29\"] some_message";
+        let mut set = HashSet::new();
+        set.insert(parse_message(input).expect("Should parse message"));
+        set.insert(parse_message(input).expect("Should parse message"));
+        assert_eq!(set.len(), 1);
+        set.insert(parse_message("<78>Mar 15 14:16:22 host1 CROND[10392]: some_message").expect("Should parse message"));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_eq_ignoring_inferred_year() {
+        let input = "<78>Jan 8 12:14:16 host1 CROND[123]: some_message";
+        // Early 2017 and early 2018
+        let a = parse_message_with_reference(input, time::at_utc(time::Timespec::new(1484000000, 0)))
+            .expect("Should parse message");
+        let b = parse_message_with_reference(input, time::at_utc(time::Timespec::new(1515600000, 0)))
+            .expect("Should parse message");
+        assert!(a != b);
+        assert!(a.eq_ignoring_inferred_year(&b));
+
+        let mut c = b.clone();
+        c.timestamp = c.timestamp.map(|ts| Timestamp::with_inferred_year(ts.secs() + 1));
+        assert!(!a.eq_ignoring_inferred_year(&c));
+        let mut c = b.clone();
+        c.msg.push('!');
+        assert!(!a.eq_ignoring_inferred_year(&c));
+
+        // RFC5424 timestamps always have a year, so it counts
+        let a = parse_message("<34>1 2003-10-11T22:14:15Z host app - - - msg").expect("Should parse message");
+        let b = parse_message("<34>1 2004-10-11T22:14:15Z host app - - - msg").expect("Should parse message");
+        assert!(a.eq_ignoring_inferred_year(&a.clone()));
+        assert!(!a.eq_ignoring_inferred_year(&b));
+
+        // An explicit year counts too, unless the other message's year was inferred
+        let a = parse_message("<78>Jan 8 12:14:16 2017 host1 CROND[123]: some_message").expect("Should parse message");
+        let b = parse_message("<78>Jan 8 12:14:16 2018 host1 CROND[123]: some_message").expect("Should parse message");
+        assert!(!a.eq_ignoring_inferred_year(&b));
+        let c = parse_message(a.to_string()).expect("Should parse message");
+        assert!(a.eq_ignoring_inferred_year(&c));
+    }
+
+    #[test]
+    fn test_to_framed() {
+        let m = parse_message("<78>Jan  8 12:14:16 host1 CROND[123]: caf\u{e9}")
+            .expect("Should parse message");
+        let framed = m.to_framed();
+        assert_eq!(framed, "43 <78>Jan  8 12:14:16 host1 CROND[123]: caf\u{e9}");
+
+        let mut input = framed.into_bytes();
+        input.extend_from_slice(m.to_framed().as_bytes());
+        let (first, rest) = parse_framed(&input).expect("Should parse frame");
+        assert!(first.eq_ignoring_inferred_year(&m));
+        let (second, rest) = parse_framed(rest).expect("Should parse frame");
+        assert!(second.eq_ignoring_inferred_year(&m));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_to_rfc5424() {
+        let m = parse_message_with_reference("<78>Jan  8 12:14:16.5 host1 CROND[123]: [meta sequenceId=\"29\"] some_message",
+                                             time::at_utc(time::Timespec::new(1484000000, 0)))
+            .expect("Should parse message");
+        let upgraded = m.to_rfc5424();
+        assert_eq!(upgraded,
+                   "<78>1 2017-01-08T12:14:16.500000Z host1 CROND 123 - [meta sequenceId=\"29\"] some_message");
+        let reparsed = parse_message(&upgraded).expect("Should parse RFC5424 message");
+        assert_eq!(reparsed.dialect(), Dialect::Rfc5424);
+        // The inferred year is written out, so it's explicit from then on
+        assert_eq!(reparsed, SyslogMessage { version: 1, timestamp: Some(Timestamp::new(1483877656)), ..m });
+
+        let m = parse_message("<13>- - - -").expect("Should parse message");
+        assert_eq!(m.to_rfc5424(), "<13>1 - - - - - -");
+        let reparsed = parse_message(m.to_rfc5424()).expect("Should parse RFC5424 message");
+        assert_eq!(reparsed, SyslogMessage { version: 1, ..m });
+
+        // Only what fits in RFC5424's header fields is written
+        let mut m = parse_message("<78>Jan  8 12:14:16.123456789 2017 host1 CROND[123]: msg").expect("Should parse message");
+        m.tag = Some(String::from("my app"));
+        m.msg_id = Some("x".repeat(40));
+        assert_eq!(m.to_rfc5424(), format!("<78>1 2017-01-08T12:14:16.123456Z host1 my_app 123 {} - msg", "x".repeat(32)));
+        m.tag = Some("a".repeat(60));
+        m.hostname = Some(String::new());
+        let reparsed = parse_message(m.to_rfc5424()).expect("Should parse RFC5424 message");
+        assert_eq!(reparsed.hostname, None);
+        assert_eq!(reparsed.tag, Some("a".repeat(48)));
+        assert_eq!(reparsed.timestamp_nanos, Some(123456000));
+    }
+
+    #[test]
+    fn test_message_body() {
+        let mut m = parse_message("<78>Jan  8 12:14:16 host1 123 CROND some_message")
+            .expect("Should parse message");
+        m.msg = String::from("CROND some_message");
+        assert_eq!(m.tag, Some(String::from("CROND")));
+        assert_eq!(m.message_body(), "some_message");
+        m.msg = String::from("CROND: some_message");
+        assert_eq!(m.message_body(), "some_message");
+        m.msg = String::from("CROND[123]: some_message");
+        assert_eq!(m.message_body(), "some_message");
+        m.msg = String::from("some_message");
+        assert_eq!(m.message_body(), "some_message");
+        m.msg = String::from("CRONDS are running");
+        assert_eq!(m.message_body(), "CRONDS are running");
+        m.msg = String::from("CROND[123 is unterminated");
+        assert_eq!(m.message_body(), "CROND[123 is unterminated");
+        m.tag = None;
+        m.msg = String::from("CROND some_message");
+        assert_eq!(m.message_body(), "CROND some_message");
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_into_json_value() {
+        let m = parse_message(r#"<78>Jan  8 12:14:16.5 2017 host1 CROND[123]: [meta seq="1"] msg"#)
+            .expect("Should parse message");
+        let value = serde_json::Value::from(&m);
+        assert_eq!(value, serde_json::to_value(&m).expect("Should encode to JSON"));
+        assert_eq!(value["facility"], "cron");
+        assert_eq!(value["proc_id"], 123);
+        assert_eq!(value["structured_data"][0]["id"], "meta");
+
+        let m = parse_message("<34>1 2003-10-11T22:14:15Z host app proc ID47 - msg").expect("Should parse message");
+        assert_eq!(serde_json::Value::from(&m), serde_json::to_value(&m).expect("Should encode to JSON"));
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_to_json_rfc3339() {
+        let m = parse_message("<78>Jan  8 12:14:16.5 2017 host1 CROND[123]: msg").expect("Should parse message");
+        assert_eq!(serde_json::to_value(&m).expect("Should encode to JSON")["timestamp"], 1483877656);
+        let value = m.to_json_rfc3339();
+        assert_eq!(value["timestamp"], "2017-01-08T12:14:16.500000000Z");
+        assert_eq!(value["timestamp_nanos"], 500000000);
+        assert_eq!(value["msg"], "msg");
+
+        let m = parse_message("<78>- host1 CROND[123]: msg").expect("Should parse message");
+        assert_eq!(m.to_json_rfc3339()["timestamp"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_age_seconds() {
+        let m = parse_message("<78>Jan  8 12:14:16 2017 host1 CROND[123]: some_message").expect("Should parse message");
+        assert_eq!(m.age_seconds(1483877656 + 90), Some(90));
+        assert_eq!(m.age_seconds(1483877656 - 5), Some(-5));
+
+        let m = parse_message("<78>- host1 CROND[123]: some_message").expect("Should parse message");
+        assert_eq!(m.age_seconds(1483877656), None);
+    }
+
+    #[test]
+    fn test_cmp_by_time() {
+        let mut messages: Vec<SyslogMessage> = [
+            "<78>Jan  8 12:14:17 2017 host1 CROND: third",
+            "<78>Jan  8 12:14:16.5 2017 host1 CROND: second",
+            "<78>- host1 CROND: first",
+            "<78>Jan  8 12:14:16 2017 host1 CROND: also first",
+            "<78>Jan  8 12:14:16 2017 host1 CROND: also second",
+        ].iter().map(|s| parse_message(s).expect("Should parse message")).collect();
+        messages.sort_by(SyslogMessage::cmp_by_time);
+        let order: Vec<&str> = messages.iter().map(|m| m.msg.as_str()).collect();
+        assert_eq!(order, vec!["first", "also first", "also second", "second", "third"]);
+        assert_eq!(messages[1].cmp_by_time(&messages[2]), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_try_from_bytes() {
+        let m = SyslogMessage::try_from(b"<13>Feb 18 20:53:31 host app: hi".as_slice()).expect("Should parse message");
+        assert_eq!(m.hostname, Some(String::from("host")));
+        assert_eq!(m.tag, Some(String::from("app")));
+        assert_eq!(m.msg, "hi");
+
+        let m = SyslogMessage::try_from(&b"<13>Feb 18 20:53:31 host app: caf\xe9"[..]).expect("Should parse message");
+        assert_eq!(m.msg, "caf\u{fffd}");
+        assert!(SyslogMessage::try_from(&b"nonsense"[..]).is_err());
+    }
+
+    #[test]
+    fn test_eq_ref() {
+        let input = r#"<78>Jan  8 12:14:16 2017 host1 CROND[123]: [meta seq="1"] msg"#;
+        let owned = parse_message(input).expect("Should parse message");
+        let borrowed = parse_message_ref(input).expect("Should parse message");
+        assert_eq!(owned, borrowed);
+        assert_eq!(borrowed, owned);
+
+        let mut other = owned.clone();
+        other.hostname = Some(String::from("host2"));
+        assert!(other != borrowed);
+        assert!(borrowed != other);
+
+        // `raw` is ignored, and `proc_id`s compare as `into_owned` would convert them
+        let mut other = owned.clone();
+        other.raw = Some(String::from(input));
+        assert_eq!(other, borrowed);
+        other.proc_id = Some(ProcIdType::Name(String::from("123")));
+        assert!(other != borrowed);
+        other.proc_id = Some(ProcIdType::PID(124));
+        assert!(other != borrowed);
+
+        let borrowed = parse_message_ref("<78>Jan  8 12:14:16 2017 host1 CROND[worker]: msg").unwrap();
+        assert_eq!(borrowed.clone().into_owned(), borrowed);
+        assert!(owned != borrowed);
+    }
+
+    #[test]
+    fn test_compact() {
+        let m = parse_message(r#"<78>Jan  8 12:14:16 2017 host1 CROND[123]: [meta seq="1"] msg"#).expect("Should parse message");
+        let encoded = serde_json::to_string(&m.compact()).expect("Should encode to JSON");
+        assert_eq!(encoded, r#"{"sev":"info","fac":"cron","pri":78,"ver":0,"ts":1483877656,"host":"host1","pid":123,"tag":"CROND","sd":[{"id":"meta","params":[["seq","1"]]}],"msg":"msg"}"#);
+        assert!(!encoded.contains("null"));
+        assert!(encoded.len() < serde_json::to_string(&m).expect("Should encode to JSON").len());
+    }
+
+    #[test]
+    fn test_repeated_count() {
+        let m = parse_message("<78>Jan  8 12:14:16 host1 last message repeated 3 times").expect("Should parse message");
+        assert_eq!(m.repeated_count(), Some(3));
+        let m = parse_message("<78>Jan  8 12:14:16 host1 syslogd: last message repeated 12 times\n").expect("Should parse message");
+        assert_eq!(m.repeated_count(), Some(12));
+        let m = parse_message("<78>Jan  8 12:14:16 host1 last message repeated 1 time").expect("Should parse message");
+        assert_eq!(m.repeated_count(), Some(1));
+
+        for s in &["<78>Jan  8 12:14:16 host1 CROND[123]: some_message",
+                   "<78>Jan  8 12:14:16 host1 last message repeated many times",
+                   "<78>Jan  8 12:14:16 host1 app: the last message repeated 3 times"] {
+            assert_eq!(parse_message(s).expect("Should parse message").repeated_count(), None);
+        }
+    }
+
+    #[test]
+    fn test_parse_kv() {
+        let m = parse_message(r#"<78>Jan  8 12:14:16 host1 fw: action=deny src=10.0.0.1 user="John \"JJ\" Smith" note= done"#)
+            .expect("Should parse message");
+        let pairs = m.parse_kv();
+        assert_eq!(pairs, vec![
+            (String::from("action"), String::from("deny")),
+            (String::from("src"), String::from("10.0.0.1")),
+            (String::from("user"), String::from(r#"John "JJ" Smith"#)),
+            (String::from("note"), String::from("")),
+        ]);
+
+        let m = parse_message(r#"<78>Jan  8 12:14:16 host1 fw: path="C:\Temp\x" =skipped"#).expect("Should parse message");
+        assert_eq!(m.parse_kv(), vec![(String::from("path"), String::from(r#"C:\Temp\x"#))]);
+
+        let m = parse_message("<78>Jan  8 12:14:16 host1 CROND[123]: no pairs here").expect("Should parse message");
+        assert_eq!(m.parse_kv(), Vec::new());
+    }
+
+    #[test]
+    fn test_redacted_msg() {
+        let m = parse_message("<78>Jan  8 12:14:16 host1 sshd: mail for <jdoe@example.com> from src=10.0.0.1: rejected")
+            .expect("Should parse message");
+        assert_eq!(m.redacted_msg(&["*@*"]), "mail for <***> from src=10.0.0.1: rejected");
+        assert_eq!(m.redacted_msg(&["*@*", "*.*.*.*"]), "mail for <***> from src=***: rejected");
+        assert_eq!(m.redacted_msg(&["jdoe@?xample.com", "mail"]), "*** for <***> from src=10.0.0.1: rejected");
+
+        // Nothing is copied without a match
+        assert_matches!(m.redacted_msg(&["*@*.org", "Mail"]), Cow::Borrowed("mail for <jdoe@example.com> from src=10.0.0.1: rejected"));
+        assert_matches!(m.redacted_msg(&[]), Cow::Borrowed(_));
+
+        // A pattern with many `*`s against a long word that almost matches doesn't take forever
+        let m = parse_message(format!("<78>Jan  8 12:14:16 host1 sshd: {} end", "a".repeat(200)))
+            .expect("Should parse message");
+        assert_matches!(m.redacted_msg(&["*a*a*a*a*a*a*a*a*a*a*b"]), Cow::Borrowed(_));
+        assert_eq!(m.redacted_msg(&["*a*a*a*a*a*a*a*a*a*a"]), "*** end");
+        assert_eq!(m.redacted_msg(&["e?d*", "**"]), "*** ***");
+    }
+
+    #[test]
+    fn test_pri_label() {
+        let mut m = parse_message("<78>Jan  8 12:14:16 host1 CROND[123]: some_message")
+            .expect("Should parse message");
+        assert_eq!(m.facility, LOG_CRON);
+        assert_eq!(m.severity, SEV_INFO);
+        assert_eq!(m.pri_label(), "cron.info");
+        m.facility = LOG_LOCAL0;
+        m.severity = SEV_EMERG;
+        assert_eq!(m.pri_label(), "local0.emerg");
+    }
+
+    #[test]
+    fn test_codes() {
+        let m = parse_message("<78>Jan 8 12:14:16 host1 123 CROND some_message").expect("Should parse");
+        assert_eq!(m.severity_code(), 6);
+        assert_eq!(m.facility_code(), 9);
+    }
+
+    #[test]
+    fn test_to_fields() {
+        let m = parse_message(r#"<78>Jan 8 12:14:16.5 2017 host1 123 CROND [meta seq="1"] some_message"#)
+            .expect("Should parse");
+        let fields = m.to_fields();
+        assert_eq!(fields.keys().cloned().collect::<Vec<_>>(), vec![
+            "facility", "hostname", "msg", "priority", "proc_id", "severity", "structured_data",
+            "tag", "timestamp", "version",
+        ]);
+        assert_eq!(fields["severity"], "info");
+        assert_eq!(fields["facility"], "cron");
+        assert_eq!(fields["priority"], "78");
+        assert_eq!(fields["timestamp"], "2017-01-08T12:14:16.500000000Z");
+        assert_eq!(fields["proc_id"], "123");
+        assert_eq!(fields["structured_data"], r#"[meta seq="1"]"#);
+        assert_eq!(fields["msg"], "some_message");
+
+        let m = parse_message("<78>- - - - some_message").expect("Should parse");
+        assert_eq!(m.to_fields().keys().cloned().collect::<Vec<_>>(), vec![
+            "facility", "msg", "priority", "severity", "version",
+        ]);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let m = parse_message("<78>Jan 8 12:14:16 host1 123 CROND some_message").expect("Should parse");
+        let rendered = m.to_string();
+        assert_eq!(rendered, "<78>Jan  8 12:14:16 host1 CROND[123]: some_message");
+        assert_eq!(parse_message(&rendered).expect("Should re-parse"), m);
+    }
+
+    #[test]
+    fn test_display_round_trip_tag_and_proc_id() {
+        let cases = [
+            ("<78>Jan 8 12:14:16 host1 CROND: some_message", "<78>Jan  8 12:14:16 host1 CROND: some_message"),
+            ("<78>Jan 8 12:14:16 host1 -[worker]: some_message", "<78>Jan  8 12:14:16 host1 -[worker]: some_message"),
+            ("<78>Jan 8 12:14:16 host1 - - some_message", "<78>Jan  8 12:14:16 host1 - - some_message"),
+        ];
+        for &(input, expected) in cases.iter() {
+            let m = parse_message(input).expect("Should parse");
+            let rendered = m.to_string();
+            assert_eq!(rendered, expected);
+            assert_eq!(parse_message(&rendered).expect("Should re-parse"), m);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip_port() {
+        for input in &["<78>Jan  8 12:14:16 host1.example.com:514 CROND[123]: some_message",
+                       "<78>Jan  8 12:14:16 [2001:db8::1]:514 CROND[123]: some_message"] {
+            let m = parse_message(input).expect("Should parse message");
+            assert_eq!(m.port, Some(514));
+            assert_eq!(m.to_string(), *input);
+        }
+    }
+
+    #[test]
+    fn test_display_day_padding() {
+        let m = parse_message("<78>Jan 18 12:14:16 host1 123 CROND some_message").expect("Should parse");
+        let rendered = m.to_string();
+        assert_eq!(rendered, "<78>Jan 18 12:14:16 host1 CROND[123]: some_message");
+        assert_eq!(parse_message(&rendered).expect("Should re-parse"), m);
+
+        for input in &["<78>Jan  8 12:14:16 host1 123 CROND some_message",
+                       "<78>Jan 08 12:14:16 host1 123 CROND some_message"] {
+            let m = parse_message(input).expect("Should parse");
+            assert_eq!(m.to_string(), "<78>Jan  8 12:14:16 host1 CROND[123]: some_message");
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip_fractional_seconds() {
+        let m = parse_message("<78>Jan 8 12:14:16.5 host1 123 CROND some_message").expect("Should parse");
+        let rendered = m.to_string();
+        assert_eq!(rendered, "<78>Jan  8 12:14:16.500000000 host1 CROND[123]: some_message");
+        assert_eq!(parse_message(&rendered).expect("Should re-parse"), m);
+    }
+
+    #[test]
+    fn test_display_round_trip_structured_data() {
+        let mut m = parse_message("<78>Jan 8 12:14:16 host1 123 CROND some_message").expect("Should parse");
+        m.structured_data.push(StructuredElement {
+            id: String::from("meta"),
+            params: vec![(String::from("note"), String::from(r#"say "hi" \ [bye]"#))].into_iter().collect(),
+        });
+        let rendered = m.to_string();
+        assert_eq!(rendered, r#"<78>Jan  8 12:14:16 host1 CROND[123]: [meta note="say \"hi\" \\ [bye\]"] some_message"#);
+        assert_eq!(parse_message(&rendered).expect("Should re-parse"), m);
+    }
+
+    #[test]
+    fn test_display_round_trip_monotonic() {
+        for input in &["<4>[   12.345678] usb 1-1: new high-speed USB device number 2",
+                       "<6>Feb 18 20:53:31 host kernel: [123456.000001] eth0: link up"] {
+            let m = parse_message(input).expect("Should parse message");
+            assert!(m.monotonic.is_some());
+            assert_eq!(m.to_string(), *input);
+            assert_eq!(parse_message(m.to_string()).expect("Should re-parse"), m);
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_timestamp_datetime() {
+        let m = parse_message("<78>Jan 8 12:14:16 2017 host1 123 CROND some_message").expect("Should parse");
+        let dt = m.timestamp_datetime().expect("Should have a timestamp");
+        assert_eq!(dt.to_rfc3339(), "2017-01-08T12:14:16+00:00");
+
+        let m = parse_message("<78>Jan 8 12:14:16.25 2017 host1 123 CROND some_message").expect("Should parse");
+        let dt = m.timestamp_datetime().expect("Should have a timestamp");
+        assert_eq!(dt.timestamp_subsec_nanos(), 250000000);
+
+        let m = parse_message("<78>- host1 123 CROND some_message").expect("Should parse");
+        assert_eq!(m.timestamp_datetime(), None);
+    }
+
+    #[test]
+    fn test_deserialization_serde() {
+        let m = parse_message(r#"<78>Jan 8 12:14:16 2017 host1 123 CROND [meta sequenceId="29"] some_message"#)
+            .expect("Should parse");
+        let encoded = serde_json::to_string(&m).expect("Should encode to JSON");
+        let decoded: SyslogMessage = serde_json::from_str(&encoded).expect("Should decode from JSON");
+        assert_eq!(decoded, m);
+
+        let m = parse_message("<1>- - CROND some_message").expect("Should parse");
+        let encoded = serde_json::to_string(&m).expect("Should encode to JSON");
+        let decoded: SyslogMessage = serde_json::from_str(&encoded).expect("Should decode from JSON");
+        assert_eq!(decoded, m);
+
+        // The inferred year isn't serialized, but the timestamp is still the same
+        let m = parse_message(r#"<78>Jan 8 12:14:16 host1 CROND[123]: some_message"#).expect("Should parse");
+        assert!(m.timestamp.expect("Should have a timestamp").year_was_inferred());
+        let encoded = serde_json::to_string(&m).expect("Should encode to JSON");
+        let decoded: SyslogMessage = serde_json::from_str(&encoded).expect("Should decode from JSON");
+        assert!(!decoded.timestamp.expect("Should have a timestamp").year_was_inferred());
+        assert_eq!(decoded, m);
+        let mut set = HashSet::new();
+        set.insert(m);
+        assert!(set.contains(&decoded));
+    }
+
+    #[test]
+    fn test_large_proc_id() {
+        assert_eq!(ProcIdType::from("4294967296"), ProcIdType::PID(4294967296));
+        assert_eq!(ProcIdType::from("99999999999999999999"), ProcIdType::Name(String::from("99999999999999999999")));
+        let m = parse_message("<78>Jan  8 12:14:16 host1 CROND[4294967296]: some_message").expect("Should parse message");
+        assert_eq!(m.proc_id, Some(ProcIdType::PID(4294967296)));
+        let encoded = serde_json::to_string(&m).expect("Should encode to JSON");
+        assert!(encoded.contains(r#""proc_id":4294967296"#));
+    }
+
+    #[test]
+    fn test_deserialize_proc_id() {
+        let pid: ProcIdType = serde_json::from_str("123").expect("Should decode a number");
+        assert_eq!(pid, ProcIdType::PID(123));
+        let pid: ProcIdType = serde_json::from_str("\"CROND\"").expect("Should decode a string");
+        assert_eq!(pid, ProcIdType::Name(String::from("CROND")));
+        let pid: ProcIdType = serde_json::from_str("4294967296").expect("Should decode a 64-bit number");
+        assert_eq!(pid, ProcIdType::PID(4294967296));
+        assert!(serde_json::from_str::<ProcIdType>("9223372036854775808").is_err());
     }
 }
@@ -1,21 +1,39 @@
-use std::str::FromStr;
-use std::str;
-use std::num;
-use std::string;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::string::{self, String};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use core::str;
+use core::num;
 
+#[cfg(feature = "std")]
 use log::*;
 
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+#[cfg(feature = "std")]
 use time;
 
 use severity;
 use facility;
-use message::{time_t, ProcIdType, SyslogMessage};
+use calendar::CalendarTime;
+use message::{time_t, MonotonicTime, StructuredElement, SyslogMessage, SyslogMessageRef,
+              Timestamp};
 
 #[derive(Debug)]
 pub enum ParseErr {
     RegexDoesNotMatchErr,
-    BadSeverityInPri,
-    BadFacilityInPri,
+    /// The PRI doesn't encode a valid severity; contains the PRI value
+    BadSeverityInPri(i32),
+    /// The PRI doesn't encode a valid facility; contains the PRI value
+    BadFacilityInPri(i32),
+    /// The PRI brackets were empty, as in `<>`
+    EmptyPriority,
+    /// The input was longer than `ParseOptions::max_len`
+    MessageTooLong,
+    /// The input was empty or only whitespace, as in a blank line
+    EmptyInput,
     UnexpectedEndOfInput,
     MonthConversionErr(String),
     TooFewDigits,
@@ -26,8 +44,158 @@ pub enum ParseErr {
     ExpectedTokenErr(char),
     IntConversionErr(num::ParseIntError),
     MissingField(&'static str),
+    /// A timestamp field had the right number of digits but an impossible value, as in `Jan 40` or
+    /// `25:00:00`; contains the name of the field
+    InvalidDateField(&'static str),
+    /// An octet-counted frame declared more bytes than were available; contains how many more
+    /// bytes are needed
+    IncompleteFrame(usize),
+    /// In strict mode, the TAG was longer than the 32 characters RFC3164 allows; contains its
+    /// length
+    TagTooLong(usize),
+    /// In strict mode, the HOSTNAME was longer than `ParseOptions::max_hostname_len`; contains its
+    /// length
+    HostnameTooLong(usize),
+    /// Another `ParseErr` along with the part of the message that was being parsed when it
+    /// happened, as in `"timestamp"`. The parse functions move this into the `ParseError`, where
+    /// `ParseError::context` returns it, so a `ParseError`'s `kind` is never `Context`.
+    Context { kind: Box<ParseErr>, at: &'static str },
 }
 
+impl ParseErr {
+    /// Annotate the error with the part of the message that was being parsed, unless it already
+    /// has a (more specific) annotation
+    pub fn context(self, at: &'static str) -> ParseErr {
+        match self {
+            ParseErr::Context { .. } => self,
+            kind => ParseErr::Context { kind: Box::new(kind), at },
+        }
+    }
+}
+
+impl fmt::Display for ParseErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseErr::Context { ref kind, at } => write!(f, "{} while parsing {}", kind, at),
+            ref kind => fmt::Debug::fmt(kind, f),
+        }
+    }
+}
+
+/// A `ParseErr` along with the byte offset into the original input at which parsing stopped
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErr,
+    pub offset: usize,
+    context: Option<&'static str>,
+}
+
+impl ParseError {
+    /// The part of the message that was being parsed when the error happened, as in
+    /// `"timestamp"`, if known
+    pub fn context(&self) -> Option<&'static str> {
+        self.context
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.context {
+            Some(at) => write!(f, "{} while parsing {} at byte {}", self.kind, at, self.offset),
+            None => write!(f, "{} at byte {}", self.kind, self.offset),
+        }
+    }
+}
+
+/// Options controlling how messages are parsed
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    /// Require exactly the RFC3164 `HEADER` grammar: single spaces between fields, a
+    /// `Mmm dd hh:mm:ss` timestamp without a year or UTC offset, a hostname, and a tag of at most
+    /// 32 characters (longer tags are rejected with `TagTooLong`). Off by default, in which case
+    /// tags of up to 255 characters are accepted.
+    pub strict: bool,
+    /// Strip C0 control characters other than tab (e.g. NUL or ESC) from the message body, so they
+    /// can't leak into downstream sinks. Off by default.
+    pub sanitize_msg: bool,
+    /// Accept messages that don't start with a `<PRI>`, as sent by some relays. Such messages get
+    /// the facility `LOG_USER` and severity `SEV_NOTICE` (the defaults from RFC3164), and a
+    /// `priority` of `MISSING_PRIORITY`. Off by default.
+    pub allow_missing_pri: bool,
+    /// Reject input longer than this many bytes with `MessageTooLong`, rather than copying all of
+    /// it into the message. Defaults to `DEFAULT_MAX_LEN`; `None` means no limit.
+    pub max_len: Option<usize>,
+    /// If either this or `default_severity` is set, a PRI that is well-formed but can't be
+    /// decoded (as in `<4096>`) doesn't fail the message: it gets this facility (or `LOG_USER` if
+    /// unset) and has `pri_recovered` set. Ignored in strict mode. `None` by default.
+    pub default_facility: Option<facility::SyslogFacility>,
+    /// The severity given to messages whose PRI couldn't be decoded; see `default_facility`.
+    /// `None` (meaning `SEV_NOTICE`) by default.
+    pub default_severity: Option<severity::SyslogSeverity>,
+    /// Skip a UTF-8 byte order mark before the PRI, as some Windows event forwarders send. On by
+    /// default.
+    pub skip_bom: bool,
+    /// Keep a copy of the input in the message's `raw` field, e.g. for audit trails. Input that
+    /// isn't valid UTF-8 is kept whole, with invalid sequences replaced by U+FFFD. Off by default.
+    pub keep_raw: bool,
+    /// Drop a single trailing `\n`, `\r\n` or `\0` from the message body, which UDP senders
+    /// often append to the datagram. On by default.
+    pub trim_trailing: bool,
+    /// The longest hostname accepted, in bytes. In strict mode, a longer hostname is rejected with
+    /// `HostnameTooLong`; otherwise it is skipped, and the message has no hostname. Defaults to
+    /// 255, the limit from RFC5424; a full DNS name can't be longer than 253.
+    pub max_hostname_len: usize,
+    /// Along with `allow_missing_pri`, take a PRI written without the angle brackets, as in
+    /// `13 Feb 18 20:53:31 ...`, as some broken senders do. Only one to three digits followed by a
+    /// space count, and only if they make a valid PRI; a timestamp never starts with a digit, so
+    /// `Feb 18 ...` is still a message without a PRI. The catch is that such a number is always
+    /// taken as the PRI, so an RFC5424 message without one, as in
+    /// `1 2003-10-11T22:14:15Z host app - - - msg`, has its VERSION taken as the PRI instead, and
+    /// is parsed as RFC3164 with `- - - msg` as its `msg`. A message without a header, as in
+    /// `404 not found`, is an error either way. Ignored in strict mode. Off by default.
+    pub bare_pri: bool,
+    /// Decodes PRI values before the standard decoding, for appliances with nonstandard facility
+    /// codes. Returning `None` falls back to the standard decoding, so only the vendor-specific
+    /// values need handling. Only PRIs of up to three digits are passed to it, unless
+    /// `default_facility` or `default_severity` is set. `None` by default.
+    pub pri_override: Option<PriOverride>,
+    /// Never lose a line: if the message can't be parsed, return it anyway, with the whole input
+    /// in `msg`, `parsed_structure` unset, the facility and severity from `default_facility` and
+    /// `default_severity` (`LOG_USER` and `SEV_NOTICE` if unset), and a `priority` of
+    /// `MISSING_PRIORITY`. Input longer than `max_len` is still rejected. Off by default.
+    pub fallback_raw_msg: bool,
+}
+
+/// A function decoding PRI values into a facility and severity, or returning `None` for the ones
+/// it doesn't handle; see `ParseOptions::pri_override`
+pub type PriOverride = fn(i32) -> Option<(facility::SyslogFacility, severity::SyslogSeverity)>;
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            strict: false,
+            sanitize_msg: false,
+            allow_missing_pri: false,
+            max_len: Some(DEFAULT_MAX_LEN),
+            default_facility: None,
+            default_severity: None,
+            skip_bom: true,
+            keep_raw: false,
+            trim_trailing: true,
+            max_hostname_len: 255,
+            bare_pri: false,
+            pri_override: None,
+            fallback_raw_msg: false,
+        }
+    }
+}
+
+/// The `priority` given to messages without a `<PRI>` when `ParseOptions::allow_missing_pri` is set
+pub const MISSING_PRIORITY: i32 = -1;
+
+/// The default `ParseOptions::max_len`, which is 64KiB, a common limit for syslog messages
+pub const DEFAULT_MAX_LEN: usize = 64 * 1024;
+
 // We parse with this super-duper-dinky hand-coded recursive descent parser because we don't really
 // have much other choice:
 //
@@ -41,9 +209,20 @@ pub enum ParseErr {
 // General convention is that the parse state is represented by a string slice named "rest"; the
 // macros will update that slice as they consume tokens.
 
+// Trace the parser's progress through the `log` crate; compiled out without `std`
+#[cfg(feature = "std")]
+macro_rules! parse_debug {
+    ($($arg:tt)*) => { debug!($($arg)*) }
+}
+
+#[cfg(not(feature = "std"))]
+macro_rules! parse_debug {
+    ($($arg:tt)*) => {{}}
+}
+
 macro_rules! maybe_expect_char {
     ($s:expr, $e: expr) => (match $s.chars().next() {
-        Some($e) => Some(&$s[1..]),
+        Some($e) => Some(&$s[$e.len_utf8()..]),
         _ => None,
     })
 }
@@ -75,7 +254,7 @@ type ParseResult<T> = Result<T, ParseErr>;
 macro_rules! take_char {
     ($e: expr, $c:expr) => {{
         $e = match $e.chars().next() {
-            Some($c) => &$e[1..],
+            Some($c) => &$e[$c.len_utf8()..],
             Some(_) => {
                 //debug!("Error with rest={:?}", $e);
                 return Err(ParseErr::ExpectedTokenErr($c));
@@ -88,55 +267,73 @@ macro_rules! take_char {
     }}
 }
 
-fn take_while<F>(input: &str, f: F, max_chars: usize) -> (&str, Option<&str>)
+// Split off the longest prefix of `input`, up to `max_chars` characters, whose characters all
+// satisfy `f`
+fn take_while<F>(input: &str, f: F, max_chars: usize) -> (&str, &str)
 where
     F: Fn(char) -> bool,
 {
-    for (idx, chr) in input.char_indices() {
-        if !f(chr) {
-            return (&input[..idx], Some(&input[idx..]));
-        }
-        if idx == max_chars {
-            return (&input[..idx], Some(&input[idx..]));
+    for (count, (idx, chr)) in input.char_indices().enumerate() {
+        if count == max_chars || !f(chr) {
+            return (&input[..idx], &input[idx..]);
         }
     }
-    ("", None)
+    // An empty slice of `input` rather than `""`, so that it still points into the input
+    (input, &input[input.len()..])
 }
 
 fn parse_pri_val(pri: i32) -> ParseResult<(severity::SyslogSeverity, facility::SyslogFacility)> {
-    let sev = severity::SyslogSeverity::from_int(pri & 0x7).ok_or(ParseErr::BadSeverityInPri)?;
-    let fac = facility::SyslogFacility::from_int(pri >> 3).ok_or(ParseErr::BadFacilityInPri)?;
+    let sev = severity::SyslogSeverity::from_int(pri & 0x7).ok_or(ParseErr::BadSeverityInPri(pri))?;
+    let fac = facility::SyslogFacility::from_int(pri >> 3).ok_or(ParseErr::BadFacilityInPri(pri))?;
     Ok((sev, fac))
 }
 
+// Decode a PRI value with `opts.pri_override` if it handles it, and `parse_pri_val` otherwise
+fn decode_pri(pri: i32, opts: &ParseOptions) -> ParseResult<(severity::SyslogSeverity, facility::SyslogFacility)> {
+    match opts.pri_override.and_then(|f| f(pri)) {
+        Some((fac, sev)) => Ok((sev, fac)),
+        None => parse_pri_val(pri),
+    }
+}
+
+// Leading zeros don't count towards a PRI's three digits, so `<0013>` is 13; keep one zero if
+// that's all there is, as in `<000>`
+fn skip_leading_zeros(s: &str) -> &str {
+    let trimmed = s.trim_start_matches('0');
+    if trimmed.len() == s.len() || trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+        trimmed
+    } else {
+        &s[s.len() - trimmed.len() - 1..]
+    }
+}
+
 fn parse_month(s: &str) -> ParseResult<(i32, &str)> {
-    let (res, rest1) = take_while(s, |c| c >= 'A' && c <= 'z', 3);
-    let rest = rest1.ok_or(ParseErr::UnexpectedEndOfInput)?;
-
-    match res {
-        "Jan" => Ok((1, rest)),
-        "Feb" => Ok((2, rest)),
-        "Mar" => Ok((3, rest)),
-        "Apr" => Ok((4, rest)),
-        "May" => Ok((5, rest)),
-        "Jun" => Ok((6, rest)),
-        "Jul" => Ok((7, rest)),
-        "Aug" => Ok((8, rest)),
-        "Sep" => Ok((9, rest)),
-        "Oct" => Ok((10, rest)),
-        "Nov" => Ok((11, rest)),
-        "Dec" => Ok((12, rest)),
+    let (res, rest) = take_while(s, |c| c >= 'A' && c <= 'z', 3);
+
+    // Some senders get the capitalization wrong, as in `JAN` or `jan`
+    match &*res.to_ascii_lowercase() {
+        "jan" => Ok((1, rest)),
+        "feb" => Ok((2, rest)),
+        "mar" => Ok((3, rest)),
+        "apr" => Ok((4, rest)),
+        "may" => Ok((5, rest)),
+        "jun" => Ok((6, rest)),
+        "jul" => Ok((7, rest)),
+        "aug" => Ok((8, rest)),
+        "sep" => Ok((9, rest)),
+        "oct" => Ok((10, rest)),
+        "nov" => Ok((11, rest)),
+        "dec" => Ok((12, rest)),
         _ => Err(ParseErr::MonthConversionErr(res.into())),
     }
 }
 
 fn parse_num(s: &str, min_digits: usize, max_digits: usize) -> ParseResult<(i32, &str)> {
-    let (res, rest1) = take_while(s, |c| c >= '0' && c <= '9', max_digits);
-    let rest = rest1.ok_or(ParseErr::UnexpectedEndOfInput)?;
-    if res.len() < min_digits {
+    let (res, rest) = take_while(s, |c| c >= '0' && c <= '9', max_digits);
+    if res.is_empty() && rest.is_empty() {
+        Err(ParseErr::UnexpectedEndOfInput)
+    } else if res.len() < min_digits {
         Err(ParseErr::TooFewDigits)
-    } else if res.len() > max_digits {
-        Err(ParseErr::TooManyDigits)
     } else {
         Ok((
             i32::from_str(res).map_err(ParseErr::IntConversionErr)?,
@@ -145,140 +342,949 @@ fn parse_num(s: &str, min_digits: usize, max_digits: usize) -> ParseResult<(i32,
     }
 }
 
-fn parse_timestamp(m: &str) -> ParseResult<(Option<time_t>, &str)> {
+// `parse_num`, except that unless `opts.strict` is set, a single leading space is skipped, as some
+// senders pad numbers with one (as in `12: 4:16`). The space stands in for a digit, so one fewer
+// is needed after it.
+fn parse_num_opts<'a>(
+    s: &'a str,
+    min_digits: usize,
+    max_digits: usize,
+    opts: &ParseOptions,
+) -> ParseResult<(i32, &'a str)> {
+    match maybe_expect_char!(s, ' ') {
+        Some(rest) if !opts.strict => parse_num(rest, min_digits.saturating_sub(1).max(1), max_digits),
+        _ => parse_num(s, min_digits, max_digits),
+    }
+}
+
+// +HH:MM or -HH:MM, returned as seconds east of UTC
+fn parse_utc_offset(s: &str) -> ParseResult<(i32, &str)> {
+    let mut rest = s;
+    let sign = match rest.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        Some(_) => return Err(ParseErr::ExpectedTokenErr('+')),
+        None => return Err(ParseErr::UnexpectedEndOfInput),
+    };
+    rest = &rest[1..];
+    let hours = take_item!(parse_num(rest, 2, 2), rest);
+    take_char!(rest, ':');
+    let minutes = take_item!(parse_num(rest, 2, 2), rest);
+    if hours > 23 || minutes > 59 {
+        return Err(ParseErr::InvalidUTCOffset);
+    }
+    Ok((sign * (hours * 3600 + minutes * 60), rest))
+}
+
+// RFC3164 timestamps don't include a year, so pick the year (relative to `now`) that places the
+// message most recently in the past. A day of slack is allowed for clock skew between hosts;
+// anything further in the future (e.g. a `Dec 31` message received on `Jan 1`) must be from the
+// previous year.
+fn infer_year(ct: &CalendarTime, now: &CalendarTime) -> ParseResult<i32> {
+    let candidate = CalendarTime { year: now.year, ..*ct };
+    if to_epoch(&candidate)? - to_epoch(now)? > 86400 {
+        now.year.checked_sub(1).ok_or(ParseErr::InvalidDateField("year"))
+    } else {
+        Ok(now.year)
+    }
+}
+
+// `ct` in seconds since the epoch, if its year is in the calendar backend's range
+fn to_epoch(ct: &CalendarTime) -> ParseResult<time_t> {
+    ct.to_epoch().ok_or(ParseErr::InvalidDateField("year"))
+}
+
+// The reference time for inferring a missing year: `now` if given, or else the current time
+#[cfg(feature = "std")]
+fn reference_time(now: Option<&CalendarTime>) -> ParseResult<CalendarTime> {
+    Ok(now.cloned().unwrap_or_else(CalendarTime::now))
+}
+
+// Without `std` there's no clock, so a missing year can only be inferred from `now`
+#[cfg(not(feature = "std"))]
+fn reference_time(now: Option<&CalendarTime>) -> ParseResult<CalendarTime> {
+    now.cloned().ok_or(ParseErr::MissingField("year"))
+}
+
+// TIME-SECFRAC: 1 to 9 digits following the seconds, normalized to nanoseconds
+fn parse_secfrac(s: &str) -> ParseResult<(u32, &str)> {
+    let (frac, rest) = parse_num(s, 1, 9)?;
+    let digits = (s.len() - rest.len()) as u32;
+    Ok((frac as u32 * 10u32.pow(9 - digits), rest))
+}
+
+// A timestamp in seconds, along with its fractional seconds in nanoseconds if it had any
+type TimestampParts = (Option<Timestamp>, Option<u32>);
+
+// parse_num only checks the number of digits, and CalendarTime::to_epoch would silently roll
+// e.g. `Jan 40` or `Feb 30` over into March, so reject impossible values up front. A second of
+// 60 is a leap second. Until an RFC3164 timestamp's year is known, it is 0, a leap year, so
+// `Feb 29` passes; check again once there is a year.
+fn check_date_fields(ct: &CalendarTime) -> ParseResult<()> {
+    if ct.month < 1 || ct.month > 12 {
+        Err(ParseErr::InvalidDateField("month"))
+    } else if ct.day < 1 || ct.day > days_in_month(ct.year, ct.month) {
+        Err(ParseErr::InvalidDateField("day"))
+    } else if ct.hour > 23 {
+        Err(ParseErr::InvalidDateField("hour"))
+    } else if ct.minute > 59 {
+        Err(ParseErr::InvalidDateField("minute"))
+    } else if ct.second > 60 {
+        Err(ParseErr::InvalidDateField("second"))
+    } else {
+        Ok(())
+    }
+}
+
+fn days_in_month(year: i32, month: i32) -> i32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Whether `m` starts like an ISO8601 date, as in `2003-10-11`
+fn starts_with_iso_date(m: &str) -> bool {
+    let b = m.as_bytes();
+    b.len() > 4 && b[..4].iter().all(u8::is_ascii_digit) && b[4] == b'-'
+}
+
+// `now` is the reference time used to infer a missing year; if `None`, the current time is used
+fn parse_timestamp<'a>(
+    m: &'a str,
+    now: Option<&CalendarTime>,
+    opts: &ParseOptions,
+) -> ParseResult<(TimestampParts, &'a str)> {
     // Jan 8 12:14:16
     let mut rest = m;
-    if rest.starts_with('-') {
-        return Ok((None, &rest[1..]));
+    if rest.starts_with('-') && !opts.strict {
+        return Ok(((None, None), &rest[1..]));
+    }
+    // Plenty of RFC3164 senders use an RFC5424-style timestamp, as in `2003-10-11T22:14:15.003Z`
+    if starts_with_iso_date(rest) && !opts.strict {
+        return parse_rfc5424_timestamp(rest);
     }
 
-    let mut tm = time::empty_tm();
-    tm.tm_mon = take_item!(parse_month(rest), rest) - 1;
+    let mut ct = CalendarTime { month: take_item!(parse_month(rest), rest), ..Default::default() };
     take_char!(rest, ' ');
-    rest = maybe_expect_char!(rest, ' ').unwrap_or(rest);
-    tm.tm_mday = take_item!(parse_num(rest, 1, 2), rest);
-    take_char!(rest, ' ');
-    tm.tm_hour = take_item!(parse_num(rest, 2, 2), rest);
+    if opts.strict {
+        // The day is always two characters wide: `Jan  8` or `Jan 18`
+        ct.day = match maybe_expect_char!(rest, ' ') {
+            Some(r) => {
+                rest = r;
+                take_item!(parse_num(rest, 1, 1), rest)
+            }
+            None => take_item!(parse_num(rest, 2, 2), rest),
+        };
+    } else {
+        ct.day = take_item!(parse_num_opts(rest, 1, 2, opts), rest);
+        // Some embedded devices write `Jan 8, 12:14:16`
+        rest = maybe_expect_char!(rest, ',').unwrap_or(rest);
+    }
+    // Gateways that bridge RFC5424 timestamps sometimes keep its `T`, as in `Feb 18T20:53:31`
+    match maybe_expect_char!(rest, 'T') {
+        Some(r) if !opts.strict => rest = r,
+        _ => take_char!(rest, ' '),
+    }
+    // Some senders put the year between the day and the time, as in `Jan 8 2017 12:14:16`. The
+    // hour only ever has two digits, so four digits here can only be a year.
+    let mut year_before_time = None;
+    if !opts.strict {
+        let mut maybe_rest = rest;
+        if let Some(year) = maybe_take_item!(parse_num(maybe_rest, 4, 4), maybe_rest) {
+            if let Some(r) = maybe_expect_char!(maybe_rest, ' ') {
+                year_before_time = Some(year);
+                rest = r;
+            }
+        }
+    }
+    ct.hour = take_item!(parse_num_opts(rest, 2, 2, opts), rest);
     take_char!(rest, ':');
 
-    tm.tm_min = take_item!(parse_num(rest, 2, 2), rest);
+    ct.minute = take_item!(parse_num_opts(rest, 2, 2, opts), rest);
     take_char!(rest, ':');
-    tm.tm_sec = take_item!(parse_num(rest, 2, 2), rest);
+    ct.second = take_item!(parse_num_opts(rest, 2, 2, opts), rest);
+    check_date_fields(&ct)?;
+
+    if opts.strict {
+        ct.year = infer_year(&ct, &reference_time(now)?)?;
+        check_date_fields(&ct)?;
+        return Ok(((Some(Timestamp::with_inferred_year(to_epoch(&ct)?)), None), rest));
+    }
+
+    let nanos = match maybe_expect_char!(rest, '.').map(parse_secfrac) {
+        Some(Ok((nanos, r))) => {
+            rest = r;
+            Some(nanos)
+        }
+        _ => None,
+    };
+
+    let utc_offset = match parse_utc_offset(maybe_expect_char!(rest, ' ').unwrap_or(rest)) {
+        Ok((offset, r)) => {
+            rest = r;
+            offset
+        }
+        Err(ParseErr::InvalidUTCOffset) => return Err(ParseErr::InvalidUTCOffset),
+        Err(_) => 0,
+    };
 
     let mut maybe_rest = rest;
     maybe_rest = maybe_expect_char!(maybe_rest, ' ').unwrap_or(maybe_rest);
-    match maybe_take_item!(parse_num(maybe_rest, 4, 4), maybe_rest) {
+    let year_was_inferred = match year_before_time {
         Some(year) => {
-            tm.tm_year = year - 1900;
-            rest = maybe_rest;
-        }
-        None => {
-            tm.tm_year = time::now().tm_year;
+            ct.year = year;
+            false
         }
+        None => match maybe_take_item!(parse_num(maybe_rest, 4, 4), maybe_rest) {
+            Some(year) => {
+                ct.year = year;
+                rest = maybe_rest;
+                false
+            }
+            None => {
+                ct.year = infer_year(&ct, &reference_time(now)?)?;
+                true
+            }
+        },
+    };
+    check_date_fields(&ct)?;
+
+    let secs = to_epoch(&ct)? - time_t::from(utc_offset);
+    let timestamp = if year_was_inferred { Timestamp::with_inferred_year(secs) } else { Timestamp::new(secs) };
+    Ok(((Some(timestamp), nanos), rest))
+}
+
+// RFC5424 TIMESTAMP: FULL-DATE "T" FULL-TIME, e.g. `2003-08-24T05:14:15.000003-07:00`
+fn parse_rfc5424_timestamp(m: &str) -> ParseResult<(TimestampParts, &str)> {
+    let mut rest = m;
+    if rest.starts_with('-') {
+        return Ok(((None, None), &rest[1..]));
     }
 
-    Ok((Some(tm.to_utc().to_timespec().sec), rest))
+    let mut ct = CalendarTime { year: take_item!(parse_num(rest, 4, 4), rest), ..Default::default() };
+    take_char!(rest, '-');
+    ct.month = take_item!(parse_num(rest, 2, 2), rest);
+    take_char!(rest, '-');
+    ct.day = take_item!(parse_num(rest, 2, 2), rest);
+    take_char!(rest, 'T');
+    ct.hour = take_item!(parse_num(rest, 2, 2), rest);
+    take_char!(rest, ':');
+    ct.minute = take_item!(parse_num(rest, 2, 2), rest);
+    take_char!(rest, ':');
+    ct.second = take_item!(parse_num(rest, 2, 2), rest);
+    check_date_fields(&ct)?;
+    let nanos = match maybe_expect_char!(rest, '.') {
+        Some(r) => {
+            rest = r;
+            Some(take_item!(parse_secfrac(rest), rest))
+        }
+        None => None,
+    };
+    let utc_offset = match maybe_expect_char!(rest, 'Z') {
+        Some(r) => {
+            rest = r;
+            0
+        }
+        None => take_item!(parse_utc_offset(rest), rest),
+    };
+
+    Ok(((Some(Timestamp::new(to_epoch(&ct)? - time_t::from(utc_offset))), nanos), rest))
+}
+
+// A NILVALUE is a bare `-` standing in for an absent field; a `-` that starts a longer token is
+// just part of that token
+fn take_nilvalue(m: &str) -> Option<&str> {
+    if m == "-" || m.starts_with("- ") {
+        Some(&m[1..])
+    } else {
+        None
+    }
 }
 
 fn parse_term(
     m: &str,
     min_length: usize,
     max_length: usize,
-) -> ParseResult<(Option<String>, &str)> {
-    if m.starts_with('-') {
-        return Ok((None, &m[1..]));
+) -> ParseResult<(Option<&str>, &str)> {
+    if let Some(rest) = take_nilvalue(m) {
+        return Ok((None, rest));
     }
+    // Everything before `idx` is printable ASCII, so slicing there is always on a char boundary
     let byte_ary = m.as_bytes();
     for (idx, chr) in byte_ary.iter().enumerate() {
         //debug!("idx={:?}, buf={:?}, chr={:?}", idx, buf, chr);
-        debug!("doo {}", chr);
+        parse_debug!("doo {}", chr);
         if *chr < 33 || *chr > 126 {
             if idx < min_length {
                 return Err(ParseErr::TooFewDigits);
             }
-            let utf8_ary = str::from_utf8(&byte_ary[..idx]).map_err(ParseErr::BaseUnicodeError)?;
-            return Ok((Some(String::from(utf8_ary)), &m[idx..]));
+            return Ok((Some(&m[..idx]), &m[idx..]));
         }
         if idx >= max_length {
-            let utf8_ary = str::from_utf8(&byte_ary[..idx]).map_err(ParseErr::BaseUnicodeError)?;
-            return Ok((Some(String::from(utf8_ary)), &m[idx..]));
+            return Ok((Some(&m[..idx]), &m[idx..]));
         }
     }
-    debug!("no term found");
+    parse_debug!("no term found");
     Ok((None, &m[0..]))
 }
 
-fn parse_hostname(m: &str) -> ParseResult<(Option<String>, &str)> {
+fn parse_hostname(m: &str, max_length: usize) -> ParseResult<(Option<&str>, &str)> {
     let min_length = 1;
-    let max_length = 255;
-    if m.starts_with('-') {
-        return Ok((None, &m[1..]));
+    if let Some(rest) = take_nilvalue(m) {
+        return Ok((None, rest));
+    }
+    // A bracketed IPv6 literal, possibly with a zone ID, as in `[fe80::1%eth0]`
+    if m.starts_with('[') {
+        let (addr, mut rest) = take_while(&m[1..], |c| c > ' ' && c <= '~' && c != ']', max_length);
+        if addr.len() < min_length {
+            return Err(ParseErr::TooFewDigits);
+        }
+        take_char!(rest, ']');
+        return Ok((Some(addr), rest));
     }
     let byte_ary = m.as_bytes();
     for (idx, chr) in byte_ary.iter().enumerate() {
         //        debug!("idx={:?}, buf={:?}, chr={:?}", idx, &m[0..idx], chr);
-        if (*chr < 33 || *chr > 126) && (*chr != 91 || *chr == 93) {
+        // Whitespace (or anything else non-printable) ends the hostname, as does either bracket
+        // around a process ID, as in `host1[123]`
+        if *chr < 33 || *chr > 126 || *chr == b'[' || *chr == b']' {
             if idx < min_length {
                 return Err(ParseErr::TooFewDigits);
             }
-            let utf8_ary = str::from_utf8(&byte_ary[..idx]).map_err(ParseErr::BaseUnicodeError)?;
-            return Ok((Some(String::from(utf8_ary)), &m[idx..]));
+            return Ok((Some(&m[..idx]), &m[idx..]));
         }
-        if idx >= max_length || *chr == 91 || *chr == 93 {
-            let utf8_ary = str::from_utf8(&byte_ary[..idx]).map_err(ParseErr::BaseUnicodeError)?;
-            return Ok((Some(String::from(utf8_ary)), &m[idx..]));
+        if idx >= max_length {
+            return Ok((Some(&m[..idx]), &m[idx..]));
         }
     }
     Err(ParseErr::UnexpectedEndOfInput)
 }
 
-fn parse_message_s(m: &str) -> ParseResult<SyslogMessage> {
-    let mut rest = m;
-    take_char!(rest, '<');
-    let prival = take_item!(parse_num(rest, 1, 3), rest);
-    take_char!(rest, '>');
-    let (sev, fac) = parse_pri_val(prival)?;
-    // let version = take_item!(parse_num(rest, 1, 2), rest); // TODO: Nuke
-    //debug!("got version {:?}, rest={:?}", version, rest);
-    let timestamp = take_item!(parse_timestamp(rest), rest);
-    debug!("timestampe: {:?}", timestamp);
-    take_char!(rest, ' ');
-    let hostname = take_item!(parse_hostname(rest), rest);
-    rest = maybe_expect_char!(rest, '[').unwrap_or(rest);
-    debug!("hostname: {:?}, rest={}", hostname, rest);
-    rest = maybe_expect_char!(rest, ' ').unwrap_or(rest);
+type HostnamePort<'a> = (Option<&'a str>, Option<u16>);
 
-    let mut maybe_rest = rest;
-    let proc_id: Option<ProcIdType> = match maybe_take_item!(parse_hostname(rest), maybe_rest) {
-        Some(Some(proc_id_r)) => {
-            debug!("pro: {}", proc_id_r);
-            let res = Some(match i32::from_str(&proc_id_r) {
-                Ok(n) => ProcIdType::PID(n),
-                Err(_) => ProcIdType::Name(proc_id_r),
+// A hostname as with `parse_hostname`, along with the port that may follow it, as in
+// `host1.example.com:514` or `[2001:db8::1]:514`. A hostname with more than one colon (but no
+// brackets) is an IPv6 address rather than a host and port. A hostname longer than `max_length`
+// is skipped whole rather than cut short, so that the rest of it isn't taken for the tag.
+fn parse_hostname_port<'a>(m: &'a str, max_length: usize) -> ParseResult<(HostnamePort<'a>, &'a str)> {
+    let (mut hostname, mut rest) = parse_hostname(m, usize::MAX)?;
+    let mut port = None;
+    if m.starts_with('[') {
+        if let Some(r) = maybe_expect_char!(rest, ':') {
+            let (digits, r) = take_while(r, |c| c.is_ascii_digit(), 5);
+            if let Ok(p) = u16::from_str(digits) {
+                port = Some(p);
+                rest = r;
+            }
+        }
+    } else if let Some(h) = hostname {
+        if let Some(idx) = h.rfind(':') {
+            let (host, p) = (&h[..idx], &h[idx + 1..]);
+            let port_digits = !p.is_empty() && p.bytes().all(|c| c.is_ascii_digit());
+            if !host.is_empty() && !host.contains(':') && port_digits {
+                if let Ok(p) = u16::from_str(p) {
+                    hostname = Some(host);
+                    port = Some(p);
+                }
+            }
+        }
+    }
+    if hostname.map_or(0, str::len) > max_length {
+        return Ok(((None, None), rest));
+    }
+    Ok(((hostname, port), rest))
+}
+
+// A bracketed PID or process name, as in `[123]` or `[CROND]`, which must be closed by a `]`. If
+// `m` doesn't start with a `[`, there isn't one, which isn't an error. A PID of `-` is a
+// NILVALUE.
+fn parse_procid(m: &str) -> ParseResult<(Option<&str>, &str)> {
+    let mut rest = match maybe_expect_char!(m, '[') {
+        Some(r) => r,
+        None => return Ok((None, m)),
+    };
+    let (procid, r) = take_while(rest, |c| c > ' ' && c <= '~' && c != '[' && c != ']', 128);
+    if procid.is_empty() {
+        return Err(ParseErr::MissingField("PROCID"));
+    }
+    rest = r;
+    take_char!(rest, ']');
+    Ok((if procid == "-" { None } else { Some(procid) }, rest))
+}
+
+// Whether `m` starts with a `TAG:` or `TAG[PID]:` rather than a hostname, as sent by devices that
+// leave the hostname out (see RFC3164 section 5.4). The colon has to end the token, so that it
+// isn't confused with a hostname with a port, or an IPv6 address.
+fn starts_with_tag(m: &str) -> bool {
+    let (tag, rest) = take_while(m, |c| c > ' ' && c <= '~' && c != '[' && c != ':', 255);
+    if tag.is_empty() {
+        return false;
+    }
+    let rest = if rest.starts_with('[') {
+        match rest.find(']') {
+            Some(idx) => &rest[idx + 1..],
+            None => return false,
+        }
+    } else {
+        rest
+    };
+    rest.starts_with(':') && (rest.len() == 1 || rest[1..].starts_with(' '))
+}
+
+// Whether `m` starts with a numeric PROCID or a NILVALUE, either of which some senders put between
+// the hostname and the tag
+fn starts_with_procid(m: &str) -> bool {
+    let token = m.split(' ').next().unwrap_or("");
+    token == "-" || (!token.is_empty() && token.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// A tag along with the PID that was attached to it, if any
+type TagParts<'a> = (Option<&'a str>, Option<&'a str>);
+
+// The `TAG[PID]:` (or just `TAG:`) that RFC3164 senders usually start the MSG with, returning the
+// tag and PID. The colon is optional after a PID, and `max_length` applies to the tag alone. A tag
+// of `-` is a NILVALUE.
+fn parse_tag<'a>(m: &'a str, max_length: usize) -> ParseResult<(TagParts<'a>, &'a str)> {
+    let (tag, mut rest) = take_while(m, |c| c > ' ' && c <= '~' && c != '[' && c != ':', max_length);
+    if tag.is_empty() {
+        return Err(ParseErr::MissingField("TAG"));
+    }
+    let pid = match maybe_expect_char!(rest, '[') {
+        Some(r) => {
+            let (pid, rest2) = take_while(r, |c| c > ' ' && c <= '~' && c != ']', 128);
+            rest = rest2;
+            take_char!(rest, ']');
+            rest = maybe_expect_char!(rest, ':').unwrap_or(rest);
+            Some(pid)
+        }
+        None => {
+            take_char!(rest, ':');
+            None
+        }
+    };
+    let tag = if tag == "-" { None } else { Some(tag) };
+    Ok(((tag, pid), rest))
+}
+
+// SD-NAME: up to 32 printable ASCII characters except '=', ' ', ']' and '"'
+fn parse_sd_id(input: &str) -> ParseResult<(&str, &str)> {
+    let (res, rest) = take_while(input, |c| c > ' ' && c <= '~' && c != '=' && c != ']' && c != '"', 32);
+    if res.is_empty() {
+        return Err(ParseErr::MissingField("SD-NAME"));
+    }
+    Ok((res, rest))
+}
+
+// A quoted PARAM-VALUE, in which '"', '\' and ']' are escaped with a backslash. Most values don't
+// have any escapes, and are returned as a slice of the input; only un-escaping needs a copy.
+fn parse_param_value<'a>(input: &'a str) -> ParseResult<(Cow<'a, str>, &'a str)> {
+    let mut rest = input;
+    take_char!(rest, '"');
+    let mut escaped = false;
+    let end = rest.char_indices()
+        .find(|&(_, chr)| {
+            let is_end = !escaped && chr == '"';
+            escaped = !escaped && chr == '\\';
+            is_end
+        })
+        .map(|(idx, _)| idx)
+        .ok_or(ParseErr::UnexpectedEndOfInput)?;
+    let (value, rest) = (&rest[..end], &rest[end + 1..]);
+    if !value.contains('\\') {
+        return Ok((Cow::Borrowed(value), rest));
+    }
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr == '\\' {
+            if let Some(&next) = chars.peek() {
+                if next == '"' || next == '\\' || next == ']' {
+                    result.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+            // Not a valid escape; the backslash is part of the value
+        }
+        result.push(chr);
+    }
+    Ok((Cow::Owned(result), rest))
+}
+
+// An element's PARAM-NAMEs and PARAM-VALUEs, borrowed from the input where possible
+type BorrowedSdParams<'a> = Vec<(&'a str, Cow<'a, str>)>;
+
+fn parse_sd_params<'a>(input: &'a str) -> ParseResult<(BorrowedSdParams<'a>, &'a str)> {
+    let mut params = Vec::new();
+    let mut top = input;
+    while let Some(rest2) = maybe_expect_char!(top, ' ') {
+        let mut rest = rest2;
+        let param_name = take_item!(parse_sd_id(rest), rest);
+        take_char!(rest, '=');
+        let param_value = take_item!(parse_param_value(rest), rest);
+        params.push((param_name, param_value));
+        top = rest;
+    }
+    Ok((params, top))
+}
+
+fn parse_sde(sde: &str) -> ParseResult<(StructuredElement, &str)> {
+    let mut rest = sde;
+    take_char!(rest, '[');
+    let id = take_item!(parse_sd_id(rest), rest);
+    let params = take_item!(parse_sd_params(rest), rest);
+    take_char!(rest, ']');
+    // Only copy the strings once the whole element has parsed
+    let params = params.into_iter().map(|(name, value)| (String::from(name), value.into_owned())).collect();
+    Ok((StructuredElement { id: String::from(id), params }, rest))
+}
+
+/// Parse one or more consecutive `[id key="value" ...]` Structured Data elements from the start of
+/// `input`, returning them along with the unconsumed remainder of the input
+pub fn parse_structured_data(input: &str) -> ParseResult<(Vec<StructuredElement>, &str)> {
+    let mut rest = input;
+    let mut elements = Vec::new();
+    loop {
+        elements.push(take_item!(parse_sde(rest), rest));
+        if !rest.starts_with('[') {
+            return Ok((elements, rest));
+        }
+    }
+}
+
+// Structured Data in an RFC3164 message, where it's only an extension and a bracketed word at the
+// start of the text (as in `[INFO] started`) is far more likely to be part of the message. So it
+// only counts if an element has a `name="value"` param or an `@`-qualified SD-ID.
+fn parse_embedded_structured_data(input: &str) -> Option<(Vec<StructuredElement>, &str)> {
+    match parse_structured_data(input) {
+        Ok((elements, rest)) if elements.iter().any(|e| !e.params().is_empty() || e.id.contains('@')) => {
+            Some((elements, rest))
+        }
+        _ => None,
+    }
+}
+
+fn parse_message_s(
+    m: &str,
+    now: Option<&CalendarTime>,
+    opts: &ParseOptions,
+) -> Result<SyslogMessage, ParseError> {
+    let msg = parse_message_ref_s(m, now, opts)?;
+    Ok(finish_message(msg, m, opts))
+}
+
+// The owned message for `msg`, parsed from `m`, with the options that only apply to owned messages
+fn finish_message(msg: SyslogMessageRef, m: &str, opts: &ParseOptions) -> SyslogMessage {
+    let mut msg = msg.into_owned();
+    if opts.sanitize_msg {
+        msg.msg.retain(|c| c == '\t' || c >= ' ');
+    }
+    if opts.keep_raw {
+        msg.raw = Some(String::from(m));
+    }
+    msg
+}
+
+fn parse_message_ref_s<'a>(
+    m: &'a str,
+    now: Option<&CalendarTime>,
+    opts: &ParseOptions,
+) -> Result<SyslogMessageRef<'a>, ParseError> {
+    if let Some(max_len) = opts.max_len {
+        if m.len() > max_len {
+            return Err(ParseError { kind: ParseErr::MessageTooLong, offset: max_len, context: None });
+        }
+    }
+    let mut rest = m;
+    let mut msg = match parse_message_at(&mut rest, now, opts) {
+        Ok(msg) => msg,
+        Err(_) if opts.fallback_raw_msg => fallback_message(m, opts),
+        Err(kind) => {
+            let (kind, context) = match kind {
+                ParseErr::Context { kind, at } => (*kind, Some(at)),
+                kind => (kind, None),
+            };
+            return Err(ParseError {
+                kind,
+                offset: rest.as_ptr() as usize - m.as_ptr() as usize,
+                context,
             });
-            // Consume the trailing space before the content part of the message
-            rest = maybe_expect_char!(maybe_rest, ' ').unwrap_or(maybe_rest);
-            res
+        }
+    };
+    if opts.trim_trailing {
+        msg.msg = trim_trailing(msg.msg);
+    }
+    Ok(msg)
+}
+
+// What `ParseOptions::fallback_raw_msg` returns for a message that didn't parse
+fn fallback_message<'a>(m: &'a str, opts: &ParseOptions) -> SyslogMessageRef<'a> {
+    SyslogMessageRef {
+        severity: opts.default_severity.unwrap_or(severity::SyslogSeverity::SEV_NOTICE),
+        facility: opts.default_facility.unwrap_or(facility::SyslogFacility::LOG_USER),
+        priority: MISSING_PRIORITY,
+        pri_recovered: false,
+        seq_no: None,
+        version: 0,
+        timestamp: None,
+        timestamp_nanos: None,
+        monotonic: None,
+        hostname: None,
+        port: None,
+        proc_id: None,
+        tag: None,
+        msg_id: None,
+        structured_data: Vec::new(),
+        msg: m,
+        parsed_structure: false,
+    }
+}
+
+// UDP senders often end the datagram with a `\n`, `\r\n` or `\0`; drop one of those
+fn trim_trailing(msg: &str) -> &str {
+    ["\r\n", "\n", "\0"].iter()
+        .find(|end| msg.ends_with(*end))
+        .map_or(msg, |end| &msg[..msg.len() - end.len()])
+}
+
+// The sequence number Cisco devices put before the PRI, as in `123: <189>...`
+fn parse_seq_no(m: &str) -> Option<(u64, &str)> {
+    let digits = m.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 || !m[digits..].starts_with(": ") {
+        return None;
+    }
+    m[..digits].parse().ok().map(|seq_no| (seq_no, &m[digits + 2..]))
+}
+
+// A kernel ring buffer timestamp, as in `[   12.345678] `: seconds since boot, padded with spaces
+// and followed by a space, which is skipped
+fn parse_monotonic(m: &str) -> Option<(MonotonicTime, &str)> {
+    let rest = m.strip_prefix('[')?.trim_start_matches(' ');
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 || digits > 10 {
+        return None;
+    }
+    let secs: u64 = rest[..digits].parse().ok()?;
+    let mut rest = &rest[digits..];
+    let mut nanos = 0;
+    if let Some(r) = rest.strip_prefix('.') {
+        let (frac, r) = parse_secfrac(r).ok()?;
+        nanos = frac;
+        rest = r;
+    }
+    let rest = rest.strip_prefix(']')?;
+    let monotonic = MonotonicTime::from_nanos(secs * 1_000_000_000 + u64::from(nanos));
+    Some((monotonic, rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+// A PRI value without its angle brackets, as in `13 Feb 18 20:53:31`, returning it and the rest of
+// the input after the space
+fn parse_bare_pri(m: &str) -> Option<(i32, &str)> {
+    let digits = m.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 || digits > 3 || !m[digits..].starts_with(' ') {
+        return None;
+    }
+    m[..digits].parse().ok().map(|prival| (prival, &m[digits + 1..]))
+}
+
+// The `<PRI>`, or its defaults if it's missing or can't be decoded and `opts` allow for that;
+// returns the PRI value, severity, facility and whether the defaults had to be used
+fn parse_pri_at(
+    rest: &mut &str,
+    opts: &ParseOptions,
+) -> ParseResult<(i32, severity::SyslogSeverity, facility::SyslogFacility, bool)> {
+    if opts.allow_missing_pri && !rest.starts_with('<') {
+        if opts.bare_pri && !opts.strict {
+            if let Some((prival, r)) = parse_bare_pri(rest) {
+                if let Ok((sev, fac)) = decode_pri(prival, opts) {
+                    *rest = r;
+                    return Ok((prival, sev, fac, false));
+                }
+            }
+        }
+        return Ok((MISSING_PRIORITY, severity::SyslogSeverity::SEV_NOTICE, facility::SyslogFacility::LOG_USER, false));
+    }
+    take_char!(*rest, '<');
+    if rest.starts_with('>') {
+        return Err(ParseErr::EmptyPriority);
+    }
+    *rest = skip_leading_zeros(rest);
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    let recoverable = !opts.strict && (opts.default_facility.is_some() || opts.default_severity.is_some());
+    if recoverable && digits > 0 && rest[digits..].starts_with('>') {
+        // Any number of digits, since a PRI that's too big is exactly what we're recovering from
+        let prival = rest[..digits].parse().unwrap_or(MISSING_PRIORITY);
+        *rest = &rest[digits + 1..];
+        return Ok(match decode_pri(prival, opts) {
+            Ok((sev, fac)) => (prival, sev, fac, false),
+            Err(_) => (prival,
+                       opts.default_severity.unwrap_or(severity::SyslogSeverity::SEV_NOTICE),
+                       opts.default_facility.unwrap_or(facility::SyslogFacility::LOG_USER),
+                       true),
+        });
+    }
+    let prival = take_item!(parse_num(rest, 1, 3), *rest);
+    take_char!(*rest, '>');
+    let (sev, fac) = decode_pri(prival, opts)?;
+    Ok((prival, sev, fac, false))
+}
+
+// `rest` is advanced as each token is consumed, so on error it still points at the place where
+// parsing stopped
+fn parse_message_at<'a>(
+    rest: &mut &'a str,
+    now: Option<&CalendarTime>,
+    opts: &ParseOptions,
+) -> ParseResult<SyslogMessageRef<'a>> {
+    if opts.skip_bom && rest.starts_with('\u{feff}') {
+        *rest = &rest['\u{feff}'.len_utf8()..];
+    }
+    if rest.trim().is_empty() {
+        return Err(ParseErr::EmptyInput);
+    }
+    let seq_no = match parse_seq_no(rest) {
+        Some((seq_no, r)) if !opts.strict => {
+            *rest = r;
+            Some(seq_no)
         }
         _ => None,
     };
-    debug!("got hostname {:?}, rest={:?}", hostname, rest);
-    let tag = take_item!(parse_term(rest, 1, 255), rest);
-    debug!("got tag {:?} rest={:?}", tag, rest);
-    rest = maybe_expect_char!(rest, ' ').unwrap_or(rest);
+    let (prival, sev, fac, pri_recovered) = parse_pri_at(rest, opts).map_err(|e| e.context("PRI"))?;
+    // RFC5424 puts a VERSION right after the PRI; an RFC3164 header goes straight to the timestamp
+    if rest.starts_with(|c: char| c.is_ascii_digit()) && !starts_with_iso_date(rest) {
+        return parse_rfc5424_at(rest, sev, fac, prival)
+            .map(|msg| SyslogMessageRef { pri_recovered, seq_no, ..msg });
+    }
+    if opts.strict {
+        return parse_rfc3164_strict_at(rest, sev, fac, prival, now, opts);
+    }
+    // A `dmesg`-style line has the time since boot where the header would be, and no header
+    if let Some((monotonic, r)) = parse_monotonic(rest) {
+        return Ok(SyslogMessageRef {
+            severity: sev,
+            facility: fac,
+            priority: prival,
+            pri_recovered,
+            seq_no,
+            version: 0,
+            timestamp: None,
+            timestamp_nanos: None,
+            monotonic: Some(monotonic),
+            hostname: None,
+            port: None,
+            proc_id: None,
+            tag: None,
+            msg_id: None,
+            structured_data: Vec::new(),
+            msg: r,
+            parsed_structure: true,
+        });
+    }
+    let (timestamp, timestamp_nanos) =
+        take_item!(parse_timestamp(rest, now, opts).map_err(|e| e.context("timestamp")), *rest);
+    parse_debug!("timestampe: {:?}", timestamp);
+    take_char!(*rest, ' ');
+    let (hostname, port) = if starts_with_tag(rest) {
+        (None, None)
+    } else {
+        take_item!(parse_hostname_port(rest, opts.max_hostname_len).map_err(|e| e.context("hostname")), *rest)
+    };
+    parse_debug!("hostname: {:?}, rest={}", hostname, rest);
+
+    // The PID may follow the hostname in brackets, as in `host1[123]`
+    let mut proc_id = None;
+    let mut maybe_rest = *rest;
+    if let Some(p) = maybe_take_item!(parse_procid(rest), maybe_rest) {
+        *rest = maybe_rest;
+        proc_id = p;
+    }
+    *rest = maybe_expect_char!(*rest, ' ').unwrap_or(*rest);
+    // ...or come on its own before the tag
+    if proc_id.is_none() && starts_with_procid(rest) {
+        proc_id = take_item!(parse_term(rest, 1, 128), *rest);
+        *rest = maybe_expect_char!(*rest, ' ').unwrap_or(*rest);
+    }
+    parse_debug!("pro: {:?}", proc_id);
+
+    // ...or, most commonly, be part of the tag, as in `sshd[123]:`. Some senders leave the tag out
+    // and put Structured Data in its place, as in `host1 [meta ...] msg`.
+    let mut structured_data = Vec::new();
+    let mut maybe_rest = *rest;
+    let tag = if let Some((elements, r)) = parse_embedded_structured_data(rest) {
+        *rest = r;
+        structured_data = elements;
+        None
+    } else {
+        match maybe_take_item!(parse_tag(rest, 255), maybe_rest) {
+            Some((tag, pid)) => {
+                *rest = maybe_rest;
+                proc_id = pid.or(proc_id);
+                tag
+            }
+            None => take_item!(parse_term(rest, 1, 255).map_err(|e| e.context("tag")), *rest),
+        }
+    };
+    parse_debug!("got tag {:?} rest={:?}", tag, rest);
+    *rest = maybe_expect_char!(*rest, ' ').unwrap_or(*rest);
+
+    // The kernel's messages start with the time since boot when relayed by a syslog daemon, as in
+    // `host kernel: [   12.345678] msg`
+    let mut monotonic = None;
+    if tag == Some("kernel") {
+        if let Some((m, r)) = parse_monotonic(rest) {
+            monotonic = Some(m);
+            *rest = r;
+        }
+    }
+
+    // Anything that doesn't parse as Structured Data is just part of the free-form message. Some
+    // senders put a nil MSGID in front of it, as in `- [meta ...]`, which goes along with it.
+    if structured_data.is_empty() {
+        let sd_start = if rest.starts_with("- [") { &rest[2..] } else { *rest };
+        if let Some((elements, r)) = parse_embedded_structured_data(sd_start) {
+            *rest = maybe_expect_char!(r, ' ').unwrap_or(r);
+            structured_data = elements;
+        }
+    }
+    parse_debug!("got structured data {:?} rest={:?}", structured_data, rest);
 
-    let msg = String::from(rest);
-    debug!("msg: {}", msg);
+    let msg = *rest;
+    parse_debug!("msg: {}", msg);
 
-    Ok(SyslogMessage {
+    Ok(SyslogMessageRef {
         severity: sev,
         facility: fac,
+        priority: prival,
+        pri_recovered,
+        seq_no,
+        version: 0,
+        timestamp,
+        timestamp_nanos,
+        monotonic,
+        hostname,
+        port,
+        proc_id,
+        tag,
+        msg_id: None,
+        structured_data,
+        msg,
+        parsed_structure: true,
+    })
+}
+
+// HEADER (after the PRI) and TAG of an RFC3164 message, with none of the usual leniency:
+//
+//   TIMESTAMP SP HOSTNAME SP TAG SP CONTENT
+//
+// where TAG may include a PID, as in `sshd[123]:`
+fn parse_rfc3164_strict_at<'a>(
+    rest: &mut &'a str,
+    severity: severity::SyslogSeverity,
+    facility: facility::SyslogFacility,
+    priority: i32,
+    now: Option<&CalendarTime>,
+    opts: &ParseOptions,
+) -> ParseResult<SyslogMessageRef<'a>> {
+    let (timestamp, timestamp_nanos) =
+        take_item!(parse_timestamp(rest, now, opts).map_err(|e| e.context("timestamp")), *rest);
+    take_char!(*rest, ' ');
+    let (hostname, r) = parse_hostname(rest, usize::MAX).map_err(|e| e.context("hostname"))?;
+    let hostname = hostname.ok_or(ParseErr::MissingField("HOSTNAME"))?;
+    if hostname.len() > opts.max_hostname_len {
+        return Err(ParseErr::HostnameTooLong(hostname.len()).context("hostname"));
+    }
+    *rest = r;
+    take_char!(*rest, ' ');
+    let (tag_token, _) = take_while(rest, |c| c > ' ' && c <= '~' && c != '[' && c != ':', usize::MAX);
+    if tag_token.len() > 32 {
+        return Err(ParseErr::TagTooLong(tag_token.len()).context("tag"));
+    }
+    let mut maybe_rest = *rest;
+    let (tag, proc_id) = match maybe_take_item!(parse_tag(rest, 32), maybe_rest) {
+        Some((tag, pid)) => {
+            *rest = maybe_rest;
+            (tag, pid)
+        }
+        None => (take_item!(parse_term(rest, 1, 32).map_err(|e| e.context("tag")), *rest), None),
+    };
+    let tag = tag.ok_or(ParseErr::MissingField("TAG"))?;
+    take_char!(*rest, ' ');
+
+    Ok(SyslogMessageRef {
+        severity,
+        facility,
+        priority,
+        pri_recovered: false,
+        seq_no: None,
         version: 0,
-        timestamp: timestamp,
-        hostname: hostname,
-        proc_id: proc_id,
-        tag: tag,
-        msg: msg,
+        timestamp,
+        timestamp_nanos,
+        monotonic: None,
+        hostname: Some(hostname),
+        port: None,
+        proc_id,
+        tag: Some(tag),
+        msg_id: None,
+        structured_data: Vec::new(),
+        msg: rest,
+        parsed_structure: true,
+    })
+}
+
+// HEADER (after the PRI) and STRUCTURED-DATA of an RFC5424 message:
+//
+//   VERSION SP TIMESTAMP SP HOSTNAME SP APP-NAME SP PROCID SP MSGID SP STRUCTURED-DATA [SP MSG]
+//
+// APP-NAME is stored as the tag
+fn parse_rfc5424_at<'a>(
+    rest: &mut &'a str,
+    severity: severity::SyslogSeverity,
+    facility: facility::SyslogFacility,
+    priority: i32,
+) -> ParseResult<SyslogMessageRef<'a>> {
+    let version = take_item!(parse_num(rest, 1, 3), *rest);
+    take_char!(*rest, ' ');
+    let (timestamp, timestamp_nanos) = take_item!(parse_rfc5424_timestamp(rest), *rest);
+    take_char!(*rest, ' ');
+    let hostname = take_item!(parse_term(rest, 1, 255), *rest);
+    take_char!(*rest, ' ');
+    let tag = take_item!(parse_term(rest, 1, 48), *rest);
+    take_char!(*rest, ' ');
+    let proc_id = take_item!(parse_term(rest, 1, 128), *rest);
+    take_char!(*rest, ' ');
+    let msg_id = take_item!(parse_term(rest, 1, 32), *rest);
+    take_char!(*rest, ' ');
+    // STRUCTURED-DATA is either the NILVALUE or one or more elements; never both
+    let structured_data = match maybe_expect_char!(*rest, '-') {
+        Some(r) => {
+            *rest = r;
+            Vec::new()
+        }
+        None => take_item!(parse_structured_data(rest), *rest),
+    };
+    *rest = maybe_expect_char!(*rest, ' ').unwrap_or(*rest);
+
+    Ok(SyslogMessageRef {
+        severity,
+        facility,
+        priority,
+        pri_recovered: false,
+        seq_no: None,
+        version,
+        timestamp,
+        timestamp_nanos,
+        monotonic: None,
+        hostname,
+        port: None,
+        proc_id,
+        tag,
+        msg_id,
+        structured_data,
+        msg: rest,
+        parsed_structure: true,
     })
 }
 
@@ -290,7 +1296,9 @@ fn parse_message_s(m: &str) -> ParseResult<SyslogMessage> {
 ///
 /// # Returns
 ///
-///  * `ParseErr` if the string is not parseable as an RFC5424 message
+///  * `ParseError` if the string is not parseable as an RFC3164 message (or, when a version number
+///    follows the PRI, an RFC5424 message); its `offset` is the byte index into `s` at which
+///    parsing stopped
 ///
 /// # Example
 ///
@@ -301,21 +1309,633 @@ fn parse_message_s(m: &str) -> ParseResult<SyslogMessage> {
 ///
 /// assert!(message.hostname.unwrap() == "host1");
 /// ```
-pub fn parse_message<S: AsRef<str>>(s: S) -> ParseResult<SyslogMessage> {
-    parse_message_s(s.as_ref())
+pub fn parse_message<S: AsRef<str>>(s: S) -> Result<SyslogMessage, ParseError> {
+    parse_message_s(s.as_ref(), None, &ParseOptions::default())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{parse_hostname, parse_message, ProcIdType};
-    use message;
-
-    use facility::SyslogFacility;
-    use severity::SyslogSeverity;
-
-    use time;
-
-    #[test]
+/// Parse a string into a `SyslogMessage` object, as controlled by `opts`
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::{parse_message_opts, ParseOptions};
+///
+/// let opts = ParseOptions { strict: true, ..Default::default() };
+///
+/// assert!(parse_message_opts("<13>Feb  5 17:32:18 host1 sshd: connected", opts.clone()).is_ok());
+/// assert!(parse_message_opts("<13>Feb 5 17:32:18  host1 sshd: connected", opts).is_err());
+/// ```
+pub fn parse_message_opts<S: AsRef<str>>(s: S, opts: ParseOptions) -> Result<SyslogMessage, ParseError> {
+    parse_message_s(s.as_ref(), None, &opts)
+}
+
+/// Parse a string into a `SyslogMessage` object, using `now` instead of the current time as the
+/// reference for inferring the year of timestamps that don't include one
+///
+/// # Example
+///
+/// ```
+/// extern crate time;
+/// extern crate syslog_rfc3164;
+///
+/// use syslog_rfc3164::parse_message_with_reference;
+///
+/// # fn main() {
+/// // 2018-01-01T00:00:30Z
+/// let now = time::at_utc(time::Timespec::new(1514764830, 0));
+/// let message = parse_message_with_reference("<78>Dec 31 23:59:00 host1 123 CROND msg", now).unwrap();
+///
+/// // 2017-12-31T23:59:00Z
+/// assert_eq!(message.timestamp.map(|ts| ts.secs()), Some(1514764740));
+/// # }
+/// ```
+#[cfg(feature = "std")]
+pub fn parse_message_with_reference<S: AsRef<str>>(s: S, now: time::Tm) -> Result<SyslogMessage, ParseError> {
+    parse_message_s(s.as_ref(), Some(&CalendarTime::from(&now)), &ParseOptions::default())
+}
+
+/// Parse a string into a `SyslogMessageRef`, which borrows its string fields from `s` instead of
+/// allocating copies of them
+///
+/// Use `SyslogMessageRef::into_owned` to convert the result into a `SyslogMessage` if it needs to
+/// outlive the input.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_message_ref;
+///
+/// let message = parse_message_ref("<78>Mar 15 14:16:22 host1 10391 CROND some_message").unwrap();
+///
+/// assert_eq!(message.hostname, Some("host1"));
+/// assert_eq!(message.msg, "some_message");
+/// ```
+pub fn parse_message_ref<'a>(s: &'a str) -> Result<SyslogMessageRef<'a>, ParseError> {
+    parse_message_ref_s(s, None, &ParseOptions::default())
+}
+
+/// Parse a string into a `SyslogMessageRef`, taking timestamps that don't include a year to be
+/// from `year`
+///
+/// Without `std` there is no clock to infer the year from, so this is how to parse such messages
+/// there.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_message_ref_in_year;
+///
+/// let message = parse_message_ref_in_year("<78>Jan 8 12:14:16 host1 CROND: some_message", 2017).unwrap();
+///
+/// // 2017-01-08T12:14:16Z
+/// assert_eq!(message.timestamp.map(|ts| ts.secs()), Some(1483877656));
+/// ```
+pub fn parse_message_ref_in_year<'a>(s: &'a str, year: i32) -> Result<SyslogMessageRef<'a>, ParseError> {
+    let end_of_year = CalendarTime { year, month: 12, day: 31, hour: 23, minute: 59, second: 59 };
+    parse_message_ref_s(s, Some(&end_of_year), &ParseOptions::default())
+}
+
+/// Parse an owned `String` into a `SyslogMessage` object
+///
+/// This is equivalent to `parse_message(&s)`: the returned message owns copies of its fields and
+/// does not borrow from `s`, which is dropped once parsing is done. It's the counterpart to
+/// `parse_message_ref` for callers that read their input into owned strings (e.g. with
+/// `BufRead::lines`) and just want to hand them over.
+pub fn parse_message_owned(s: String) -> Result<SyslogMessage, ParseError> {
+    parse_message_s(&s, None, &ParseOptions::default())
+}
+
+/// Whether `s` parses as a message, as with `parse_message(s).is_ok()`, but without copying its
+/// fields into a `SyslogMessage`
+///
+/// This runs the same parser as `parse_message_ref`, so it checks the whole message (PRI,
+/// timestamp, hostname and so on) with the default `ParseOptions`, and gives the same answer as
+/// `parse_message`; only the copying is skipped, which makes it cheaper for pre-filtering.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::is_valid_message;
+///
+/// assert!(is_valid_message("<78>Mar 15 14:16:22 host1 CROND: ok"));
+/// assert!(!is_valid_message("nonsense"));
+/// ```
+pub fn is_valid_message(s: &str) -> bool {
+    parse_message_ref_s(s, None, &ParseOptions::default()).is_ok()
+}
+
+/// Parse a string into an existing `SyslogMessage`, reusing its `String` buffers
+///
+/// In a loop over many messages, this saves allocating new `hostname`, `tag`, `msg_id` and `msg`
+/// strings for each one: their contents are cleared and replaced, keeping the capacity. Optional
+/// fields only have a buffer to reuse if they are `Some`, so they should start out as
+/// `Some(String::new())` (or be left over from the previous message); a field the new message
+/// doesn't have is set to `None`, and its buffer is dropped. The other fields are overwritten,
+/// and `raw` is cleared. If parsing fails, `out` is left unchanged.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::{parse_into, parse_message};
+///
+/// let mut message = parse_message("<78>Mar 15 14:16:22 host1 CROND: first").unwrap();
+/// parse_into("<78>Mar 15 14:16:23 host2 CROND: second", &mut message).unwrap();
+///
+/// assert_eq!(message.hostname, Some(String::from("host2")));
+/// assert_eq!(message.msg, "second");
+/// ```
+pub fn parse_into(s: &str, out: &mut SyslogMessage) -> Result<(), ParseError> {
+    parse_message_ref_s(s, None, &ParseOptions::default())?.write_into(out);
+    Ok(())
+}
+
+/// Parse a string into a `SyslogMessage` object whose `raw` field holds a copy of the input
+///
+/// This is equivalent to `parse_message_opts` with `ParseOptions::keep_raw` set.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_message_keep_raw;
+///
+/// let message = parse_message_keep_raw("<13>Feb 18 20:53:31 host app: hi").unwrap();
+///
+/// assert_eq!(message.raw, Some(String::from("<13>Feb 18 20:53:31 host app: hi")));
+/// ```
+pub fn parse_message_keep_raw<S: AsRef<str>>(s: S) -> Result<SyslogMessage, ParseError> {
+    parse_message_s(s.as_ref(), None, &ParseOptions { keep_raw: true, ..Default::default() })
+}
+
+/// Parse the first message in `input`, returning it along with the unconsumed remainder of the
+/// input
+///
+/// RFC3164 has no explicit message terminator, so the message body runs to the end of the input,
+/// unless there is a newline, in which case it stops there; everything after the newline is
+/// returned as the remainder.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_message_partial;
+///
+/// let (message, rest) = parse_message_partial("<78>Mar 15 14:16:22 host1 10391 CROND first\n<78>Mar 15").unwrap();
+///
+/// assert_eq!(message.msg, "first");
+/// assert_eq!(rest, "<78>Mar 15");
+/// ```
+pub fn parse_message_partial(input: &str) -> Result<(SyslogMessage, &str), ParseError> {
+    let (m, rest) = match input.find('\n') {
+        Some(idx) => (&input[..idx], &input[idx + 1..]),
+        None => (input, &input[input.len()..]),
+    };
+    Ok((parse_message_s(m, None, &ParseOptions::default())?, rest))
+}
+
+/// Parse every line of `input` as a message, collecting the ones that parse as well as the errors
+/// from the ones that don't, along with their (1-based) line numbers
+///
+/// Unlike calling `parse_message` on each line with `?`, this never stops at the first error.
+/// Empty lines are skipped, and a `\r` before the `\n` is ignored.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_all;
+///
+/// let (messages, errors) = parse_all("<78>Mar 15 14:16:22 host1 CROND: ok\nnonsense\n");
+///
+/// assert_eq!(messages.len(), 1);
+/// assert_eq!(errors.len(), 1);
+/// assert_eq!(errors[0].0, 2);
+/// ```
+pub fn parse_all(input: &str) -> (Vec<SyslogMessage>, Vec<(usize, ParseError)>) {
+    let mut messages = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match parse_message_s(line, None, &ParseOptions::default()) {
+            Ok(msg) => messages.push(msg),
+            Err(e) => errors.push((idx + 1, e)),
+        }
+    }
+    (messages, errors)
+}
+
+/// An error from `parse_reader`: either reading failed, or a line didn't parse
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "error reading messages: {}", e),
+            ReadError::Parse(ref e) => e.fmt(f),
+        }
+    }
+}
+
+/// Lazily parse each line read from `r` as a message, without reading all of the input into
+/// memory first
+///
+/// Empty lines are skipped, and a `\r` before the `\n` is ignored. As with
+/// `parse_message_bytes`, the message body doesn't have to be valid UTF-8. A line that doesn't
+/// parse yields a `ReadError::Parse`, and reading carries on with the next line; an I/O error
+/// yields a `ReadError::Io`. Only available with the `std` feature.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use syslog_rfc3164::parse_reader;
+///
+/// let input = Cursor::new("<78>Mar 15 14:16:22 host1 CROND: one\n<78>Mar 15 14:16:23 host1 CROND: two\n");
+/// let messages: Vec<_> = parse_reader(input).map(Result::unwrap).collect();
+///
+/// assert_eq!(messages[1].msg, "two");
+/// ```
+#[cfg(feature = "std")]
+pub fn parse_reader<R: BufRead>(r: R) -> impl Iterator<Item = Result<SyslogMessage, ReadError>> {
+    r.split(b'\n')
+        .map(|line| line.map(|mut line| {
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            line
+        }))
+        .filter(|line| line.as_ref().map(|l| !l.is_empty()).unwrap_or(true))
+        .map(|line| match line {
+            Ok(line) => parse_message_bytes_s(&line, &ParseOptions::default()).map_err(ReadError::Parse),
+            Err(e) => Err(ReadError::Io(e)),
+        })
+}
+
+/// Parse a buffer holding several messages run together, as in `<13>...msg1<14>...msg2`, which
+/// happens when UDP datagrams get coalesced (e.g. by `recvmmsg` or a misconfigured sender)
+///
+/// RFC3164 has no message terminator, so this is a heuristic: the input is split before every
+/// `<` that looks like the start of a fresh PRI, i.e. is followed by at most three digits and a
+/// `>`. A message body that happens to contain something like `<12>` is therefore split in two,
+/// and a message whose PRI has extra leading zeros, as in `<0013>`, is not split off from the one
+/// before it. The results are in input order, and error offsets are relative to `input`.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_concatenated;
+///
+/// let results = parse_concatenated("<13>Mar 15 14:16:22 host1 app: one<14>Mar 15 14:16:23 host1 app: two");
+///
+/// assert_eq!(results.len(), 2);
+/// assert_eq!(results[1].as_ref().unwrap().msg, "two");
+/// ```
+pub fn parse_concatenated(input: &str) -> Vec<Result<SyslogMessage, ParseError>> {
+    let mut starts = input.char_indices()
+        .filter(|&(idx, c)| idx > 0 && c == '<' && starts_with_pri(&input[idx..]))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<usize>>();
+    starts.insert(0, 0);
+    starts.iter().enumerate().map(|(i, &start)| {
+        let end = starts.get(i + 1).cloned().unwrap_or(input.len());
+        parse_message_s(&input[start..end], None, &ParseOptions::default()).map_err(|e| ParseError {
+            offset: e.offset + start,
+            ..e
+        })
+    }).collect()
+}
+
+/// Parse newline-separated messages, some of which span several lines, as stack traces do
+///
+/// Every line that starts with a PRI (a `<`, one to three digits and a `>`) starts a new message.
+/// Any other line is a continuation, and is appended to the previous message's `msg` after a
+/// `\n`. A continuation of a message that failed to parse is dropped along with it, and one at the
+/// very start of the input is parsed as a message of its own (which fails). Empty lines are
+/// skipped, and a `\r` before the `\n` is ignored. The results are in input order, and error
+/// offsets are relative to `input`.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_multiline;
+///
+/// let results = parse_multiline("<11>Mar 15 14:16:22 host1 app: oops\n\tat Main.main\n<14>Mar 15 14:16:23 host1 app: ok");
+///
+/// assert_eq!(results.len(), 2);
+/// assert_eq!(results[0].as_ref().unwrap().msg, "oops\n\tat Main.main");
+/// ```
+pub fn parse_multiline(input: &str) -> Vec<Result<SyslogMessage, ParseError>> {
+    let mut results: Vec<Result<SyslogMessage, ParseError>> = Vec::new();
+    let mut start = 0;
+    for line in input.split('\n') {
+        let line_start = start;
+        start += line.len() + 1;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        if !starts_with_pri(line) && !results.is_empty() {
+            if let Some(&mut Ok(ref mut msg)) = results.last_mut() {
+                msg.msg.push('\n');
+                msg.msg.push_str(line);
+            }
+            continue;
+        }
+        results.push(parse_message_s(line, None, &ParseOptions::default()).map_err(|e| ParseError {
+            offset: e.offset + line_start,
+            ..e
+        }));
+    }
+    results
+}
+
+// A `<`, one to three digits and a `>`
+fn starts_with_pri(s: &str) -> bool {
+    if !s.starts_with('<') {
+        return false;
+    }
+    let digits = s[1..].bytes().take_while(|b| b.is_ascii_digit()).count();
+    (1..=3).contains(&digits) && s[1 + digits..].starts_with('>')
+}
+
+/// Parse a byte buffer (e.g., a UDP datagram) into a `SyslogMessage` object
+///
+/// The structured part of an RFC3164 message is pure ASCII, so only the free-form message body
+/// is allowed to contain invalid UTF-8; it is converted lossily (invalid sequences become
+/// U+FFFD) instead of rejecting the whole message. Invalid UTF-8 anywhere before the body,
+/// including in the tag, fails with `BaseUnicodeError`.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_message_bytes;
+///
+/// let message = parse_message_bytes(b"<13>Feb 18 20:53:31 host1 123 app caf\xe9").unwrap();
+///
+/// assert_eq!(message.msg, "caf\u{fffd}");
+/// ```
+pub fn parse_message_bytes(b: &[u8]) -> Result<SyslogMessage, ParseError> {
+    parse_message_bytes_s(b, &ParseOptions::default())
+}
+
+fn parse_message_bytes_s(b: &[u8], opts: &ParseOptions) -> Result<SyslogMessage, ParseError> {
+    let utf8_err = match str::from_utf8(b) {
+        Ok(s) => return parse_message_s(s, None, opts),
+        Err(e) => e,
+    };
+    // Replacement characters are longer than the bytes they replace, so check the length up front
+    if let Some(max_len) = opts.max_len {
+        if b.len() > max_len {
+            return Err(ParseError { kind: ParseErr::MessageTooLong, offset: max_len, context: None });
+        }
+    }
+    let invalid_header = ParseError {
+        kind: ParseErr::BaseUnicodeError(utf8_err),
+        offset: utf8_err.valid_up_to(),
+        context: None,
+    };
+    // Up to the first invalid byte, offsets into `s` are offsets into `b`
+    let s = String::from_utf8_lossy(b);
+    let unlimited = ParseOptions { max_len: None, ..opts.clone() };
+    let msg = match parse_message_ref_s(&s, None, &unlimited) {
+        Ok(msg) => msg,
+        Err(ref e) if e.offset > utf8_err.valid_up_to() => return Err(invalid_header),
+        Err(e) => return Err(e),
+    };
+    // The invalid bytes have to be in the body, and not glued onto the end of a header field, as
+    // in a Latin-1 tag the parser stopped short of. A tag's colon ends the header as well as a
+    // space does, as in `app:caf\xe9`.
+    let header = &s[..msg.msg.as_ptr() as usize - s.as_ptr() as usize];
+    let header_ended =
+        header.is_empty() || header.ends_with(' ') || (msg.tag.is_some() && header.ends_with(':'));
+    if header.len() > utf8_err.valid_up_to() || (header.len() == utf8_err.valid_up_to() && !header_ended) {
+        return Err(invalid_header);
+    }
+    Ok(finish_message(msg, &s, opts))
+}
+
+/// Parse a single octet-counted frame (as described in
+/// [RFC 6587](https://tools.ietf.org/html/rfc6587#section-3.4.1)) from the start of `input`
+///
+/// Syslog carried over TCP is commonly framed as `MSG-LEN SP SYSLOG-MSG`, where `MSG-LEN` is the
+/// length of the message in bytes. The message is parsed as with `parse_message_bytes`, and
+/// returned along with whatever follows it in `input`.
+///
+/// # Returns
+///
+///  * `ParseErr::IncompleteFrame` if the declared length is larger than the rest of `input`; the
+///    caller should read more data and try again
+///  * `ParseErr::UnexpectedEndOfInput` if `input` ends before the length has been read
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::parse_framed;
+///
+/// let input = b"35 <13>Feb 18 20:53:31 host 123 app hi34 <14>Feb 18 20:53:32 host 12 app hi";
+/// let (first, rest) = parse_framed(input).unwrap();
+/// let (second, rest) = parse_framed(rest).unwrap();
+///
+/// assert_eq!(first.priority, 13);
+/// assert_eq!(second.priority, 14);
+/// assert!(rest.is_empty());
+/// ```
+pub fn parse_framed(input: &[u8]) -> Result<(SyslogMessage, &[u8]), ParseError> {
+    let digits = input.iter().take_while(|c| c.is_ascii_digit()).count();
+    let frame_err = |kind, offset| ParseError { kind, offset, context: None };
+    if digits == input.len() {
+        return Err(frame_err(ParseErr::UnexpectedEndOfInput, digits));
+    } else if digits == 0 {
+        return Err(frame_err(ParseErr::TooFewDigits, 0));
+    } else if input[digits] != b' ' {
+        return Err(frame_err(ParseErr::ExpectedTokenErr(' '), digits));
+    }
+    let len = usize::from_str(str::from_utf8(&input[..digits]).unwrap())
+        .map_err(|e| frame_err(ParseErr::IntConversionErr(e), 0))?;
+    let header_len = digits + 1;
+    let body = &input[header_len..];
+    if len > body.len() {
+        return Err(frame_err(ParseErr::IncompleteFrame(len - body.len()), input.len()));
+    }
+    let (frame, rest) = body.split_at(len);
+    let msg = parse_message_bytes(frame).map_err(|e| ParseError { offset: e.offset + header_len, ..e })?;
+    Ok((msg, rest))
+}
+
+/// Splits newline-delimited messages (the "non-transparent framing" of
+/// [RFC 6587](https://tools.ietf.org/html/rfc6587#section-3.4.2)) out of a buffer that is filled
+/// as data arrives, e.g. from a TCP stream
+///
+/// This is meant to be wrapped in whatever codec abstraction the caller's I/O library provides,
+/// without this crate having to depend on it.
+///
+/// # Example
+///
+/// ```
+/// use syslog_rfc3164::Rfc3164Decoder;
+///
+/// let mut decoder = Rfc3164Decoder::new();
+/// let mut buf = b"<13>Feb 18 20:53:31 host 123 app hi\n<14>Feb 18 20:53:".to_vec();
+///
+/// assert_eq!(decoder.decode(&mut buf).unwrap().unwrap().priority, 13);
+/// assert!(decoder.decode(&mut buf).unwrap().is_none());
+///
+/// buf.extend_from_slice(b"32 host 12 app hi\n");
+/// assert_eq!(decoder.decode(&mut buf).unwrap().unwrap().priority, 14);
+/// assert!(buf.is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Rfc3164Decoder {
+    opts: ParseOptions,
+    // How much of the buffer is already known not to contain a newline
+    searched: usize,
+}
+
+impl Rfc3164Decoder {
+    /// A decoder that parses messages as `parse_message_bytes` does
+    pub fn new() -> Rfc3164Decoder {
+        Rfc3164Decoder::default()
+    }
+
+    /// A decoder that parses messages as controlled by `opts`
+    pub fn with_options(opts: ParseOptions) -> Rfc3164Decoder {
+        Rfc3164Decoder { opts, searched: 0 }
+    }
+
+    /// Remove the first complete message (along with its `\n` or `\r\n` terminator) from the
+    /// front of `buf` and parse it, or return `Ok(None)` if `buf` doesn't hold a complete message
+    /// yet. Empty lines are skipped.
+    ///
+    /// A message that fails to parse is still removed from `buf`, so decoding can carry on with the
+    /// next one. If `buf` grows past `ParseOptions::max_len` without a newline, it is discarded
+    /// and `MessageTooLong` returned.
+    ///
+    /// Data should only be appended to `buf` between calls. If you remove data from it yourself,
+    /// pass it to `decode` before appending more, or a newline in the new data may be missed until
+    /// the next one arrives.
+    pub fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<SyslogMessage>, ParseError> {
+        loop {
+            // The caller may have cleared or shrunk `buf` since the last call, so search all of it
+            if self.searched > buf.len() {
+                self.searched = 0;
+            }
+            let end = match buf[self.searched..].iter().position(|&b| b == b'\n') {
+                Some(idx) => self.searched + idx,
+                None => {
+                    self.searched = buf.len();
+                    if let Some(max_len) = self.opts.max_len {
+                        if buf.len() > max_len {
+                            buf.clear();
+                            self.searched = 0;
+                            return Err(ParseError { kind: ParseErr::MessageTooLong, offset: max_len, context: None });
+                        }
+                    }
+                    return Ok(None);
+                }
+            };
+            let frame: Vec<u8> = buf.drain(..end + 1).collect();
+            self.searched = 0;
+            let line = &frame[..end];
+            let line = if line.ends_with(b"\r") { &line[..end - 1] } else { line };
+            if !line.is_empty() {
+                return parse_message_bytes_s(line, &self.opts).map(Some);
+            }
+        }
+    }
+}
+
+/// The building blocks `parse_message` is made of, for parsing nonstandard formats
+///
+/// Each function parses one field from the start of its input and returns it along with the
+/// unconsumed remainder of the input, so they can be chained, as in:
+///
+/// ```
+/// use syslog_rfc3164::parser::fields::{parse_hostname, parse_tag};
+///
+/// let (hostname, rest) = parse_hostname("host1 sshd[123]: hi").unwrap();
+/// let ((tag, pid), rest) = parse_tag(&rest[1..]).unwrap();
+///
+/// assert_eq!((hostname, tag, pid, rest), (Some("host1"), Some("sshd"), Some("123"), " hi"));
+/// ```
+///
+/// They are as lenient as `parse_message`, except where noted.
+pub mod fields {
+    use super::{ParseErr, ParseOptions, TagParts, TimestampParts};
+
+    pub use super::parse_structured_data;
+
+    /// A three-letter month name, as in `Jan`, returned as 1 through 12. Capitalization is ignored.
+    pub fn parse_month(s: &str) -> Result<(i32, &str), ParseErr> {
+        super::parse_month(s)
+    }
+
+    /// A decimal number of between `min_digits` and `max_digits` digits; any digits after the
+    /// first `max_digits` are left in the remainder
+    pub fn parse_num(s: &str, min_digits: usize, max_digits: usize) -> Result<(i32, &str), ParseErr> {
+        super::parse_num(s, min_digits, max_digits)
+    }
+
+    /// `parse_num`, except that unless `opts.strict` is set, a single leading space is skipped and
+    /// counts as one of the digits, as in a space-padded `12: 4:16`
+    pub fn parse_num_opts<'a>(
+        s: &'a str,
+        min_digits: usize,
+        max_digits: usize,
+        opts: &ParseOptions,
+    ) -> Result<(i32, &'a str), ParseErr> {
+        super::parse_num_opts(s, min_digits, max_digits, opts)
+    }
+
+    /// An RFC3164 timestamp, as in `Jan  8 12:14:16`, or one of the variants `parse_message`
+    /// accepts unless `opts.strict` is set (an RFC3339 timestamp, fractional seconds, a UTC
+    /// offset or a year). Returns the timestamp and its fractional seconds in nanoseconds, or
+    /// `None`s for a `-`.
+    ///
+    /// A missing year is inferred from the current date, as with `parse_message`, so without the
+    /// `std` feature a timestamp has to include one.
+    pub fn parse_timestamp<'a>(
+        s: &'a str,
+        opts: &ParseOptions,
+    ) -> Result<(TimestampParts, &'a str), ParseErr> {
+        super::parse_timestamp(s, None, opts)
+    }
+
+    /// A hostname, IPv4 address or bracketed IPv6 address, as in `[2001:db8::1]`, of at most 255
+    /// bytes. A `-` gives `None`.
+    pub fn parse_hostname(s: &str) -> Result<(Option<&str>, &str), ParseErr> {
+        super::parse_hostname(s, 255)
+    }
+
+    /// A `TAG:` or `TAG[PID]:`, returning the tag and PID. The colon is optional after a PID, and
+    /// the tag may be up to 255 characters; a tag of `-` gives `None`.
+    pub fn parse_tag<'a>(s: &'a str) -> Result<(TagParts<'a>, &'a str), ParseErr> {
+        super::parse_tag(s, 255)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{infer_year, is_valid_message, parse_all, parse_concatenated, parse_into, parse_multiline, parse_reader, ReadError, parse_framed, parse_param_value, parse_month, parse_num, parse_num_opts, parse_hostname, parse_hostname_port, parse_message, parse_message_opts,
+                parse_message_ref, parse_message_ref_in_year, parse_message_with_reference, parse_message_bytes,
+                parse_message_keep_raw, parse_message_owned, parse_message_partial, parse_procid, parse_structured_data, ParseErr, ParseError, ParseOptions,
+                Rfc3164Decoder, DEFAULT_MAX_LEN, MISSING_PRIORITY};
+    use calendar::CalendarTime;
+    use message::{self, ProcIdType, Timestamp};
+    use std::borrow::Cow;
+    use std::io::{self, BufReader, Cursor, Read};
+    use serde_json;
+
+    use facility::SyslogFacility;
+    use severity::SyslogSeverity;
+
+    use time;
+
+    #[test]
     fn test_simple() {
         let msg = parse_message("<1>- - - - - -").expect("Should parse empty message");
         assert!(msg.facility == SyslogFacility::LOG_KERN);
@@ -325,59 +1945,1552 @@ mod tests {
     }
 
     #[test]
-    fn test_timestamp_without_year() {
-        let msg: message::SyslogMessage =
-            parse_message("<1>Jan 8 12:14:16 host tag -").expect("Should parse empty message");
-        let mut tm = time::empty_tm();
-        tm.tm_mon = 0;
-        tm.tm_mday = 8;
-        tm.tm_hour = 12;
-        tm.tm_min = 14;
-        tm.tm_sec = 16;
-        tm.tm_year = time::now().tm_year;
+    fn test_timestamp_without_year() {
+        let msg: message::SyslogMessage =
+            parse_message("<1>Jan 8 12:14:16 host tag -").expect("Should parse empty message");
+        let mut tm = time::empty_tm();
+        tm.tm_mon = 0;
+        tm.tm_mday = 8;
+        tm.tm_hour = 12;
+        tm.tm_min = 14;
+        tm.tm_sec = 16;
+        tm.tm_year = time::now().tm_year;
+        if tm.to_timespec().sec - time::now().to_timespec().sec > 86400 {
+            tm.tm_year -= 1;
+        }
+
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(tm.to_utc().to_timespec().sec));
+        assert_eq!(msg.hostname, Some("host".into()));
+    }
+
+    #[test]
+    fn test_infer_year() {
+        // 2018-01-01T00:00:30Z
+        let now = CalendarTime { year: 2018, month: 1, day: 1, hour: 0, minute: 0, second: 30 };
+
+        // Dec 31 23:59:00, read just after midnight on Jan 1
+        let mut ct = CalendarTime { year: 0, month: 12, day: 31, hour: 23, minute: 59, second: 0 };
+        assert_matches!(infer_year(&ct, &now), Ok(2017));
+
+        // Jan 1 00:00:10, read twenty seconds later
+        ct.month = 1;
+        ct.day = 1;
+        ct.hour = 0;
+        ct.minute = 0;
+        ct.second = 10;
+        assert_matches!(infer_year(&ct, &now), Ok(2018));
+
+        // Jan 1 12:00:00 is in the future, but within the allowed clock skew
+        ct.hour = 12;
+        assert_matches!(infer_year(&ct, &now), Ok(2018));
+
+        // Jan 3 can't have happened yet, so it must have been last year
+        ct.day = 3;
+        assert_matches!(infer_year(&ct, &now), Ok(2017));
+    }
+
+    #[test]
+    fn test_timestamp_day_padding() {
+        for input in &["<1>Jan  8 12:14:16 2017 host - - -",
+                       "<1>Jan 08 12:14:16 2017 host - - -",
+                       "<1>Jan 8 12:14:16 2017 host - - -"] {
+            let msg = parse_message(input).expect("Should parse single-digit day");
+            assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+        }
+        let msg = parse_message("<1>Jan 18 12:14:16 2017 host - - -").expect("Should parse double-digit day");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1484741656));
+    }
+
+    #[test]
+    fn test_timestamp_with_reference() {
+        // 2010-06-01T00:00:00Z
+        let now = time::at_utc(time::Timespec::new(1275350400, 0));
+        let msg = parse_message_with_reference("<1>Jan 8 12:14:16 host - - -", now)
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1262952856));
+
+        // An explicit year wins over the reference
+        let msg = parse_message_with_reference("<1>Jan 8 12:14:16 1995 host - - -", now)
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+
+        // 2018-01-01T00:00:30Z
+        let now = time::at_utc(time::Timespec::new(1514764830, 0));
+        let msg = parse_message_with_reference("<1>Dec 31 23:59:00 host - - -", now)
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1514764740));
+    }
+
+    #[test]
+    fn test_timestamp_with_year_in_message() {
+        let msg = parse_message("<1>Jan 8 12:14:16 1995 host - - - -")
+            .expect("Should parse empty message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+    }
+
+    #[test]
+    fn test_timestamp_with_year_before_time() {
+        let msg = parse_message("<1>Jan 8 1995 12:14:16 host - - - -")
+            .expect("Should parse year between day and time");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+        assert_eq!(msg.timestamp.map(|ts| ts.year_was_inferred()), Some(false));
+        assert_eq!(msg.hostname, Some("host".into()));
+
+        let after = parse_message("<1>Jan 8 12:14:16 1995 host - - - -").unwrap();
+        assert_eq!(msg.timestamp, after.timestamp);
+
+        let msg = parse_message("<1>Jan 8, 1995 12:14:16 host - - - -").unwrap();
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert!(parse_message_opts("<1>Jan  8 1995 12:14:16 host tag: -", strict).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_with_t_separator() {
+        let msg = parse_message_ref_in_year("<13>Feb 18T20:53:31 host app: hi", 2017).unwrap();
+        assert_eq!(msg.timestamp, parse_message_ref_in_year("<13>Feb 18 20:53:31 host app: hi", 2017).unwrap().timestamp);
+        assert_eq!(msg.hostname, Some("host"));
+        assert_eq!(msg.msg, "hi");
+
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        let err = parse_message_opts("<13>Feb 18T20:53:31 host app: hi", strict).unwrap_err();
+        assert_matches!(err.kind, ParseErr::ExpectedTokenErr(' '));
+    }
+
+    #[test]
+    fn test_timestamp_with_utc_offset() {
+        let msg = parse_message("<1>Jan 8 14:14:16 +02:00 1995 host - - -")
+            .expect("Should parse message with positive offset");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+        assert_eq!(msg.hostname, Some("host".into()));
+
+        let msg = parse_message("<1>Jan 8 10:44:16-01:30 1995 host - - -")
+            .expect("Should parse message with negative offset");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(789567256));
+    }
+
+    #[test]
+    fn test_timestamp_with_fractional_seconds() {
+        let msg = parse_message("<1>Jan 8 12:14:16.123 2017 host - - -")
+            .expect("Should parse message with fractional seconds");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+        assert_eq!(msg.timestamp_nanos, Some(123000000));
+        assert_eq!(msg.hostname, Some("host".into()));
+
+        let msg = parse_message("<1>Jan 8 12:14:16.000000001+01:00 2017 host - - -")
+            .expect("Should parse message with nanoseconds and an offset");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483874056));
+        assert_eq!(msg.timestamp_nanos, Some(1));
+
+        let msg = parse_message("<1>Jan 8 12:14:16 2017 host - - -").expect("Should parse message");
+        assert_eq!(msg.timestamp_nanos, None);
+
+        let msg = parse_message("<34>1 2003-08-24T05:14:15.000003-07:00 192.0.2.1 myproc 8710 - - msg")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.timestamp_nanos, Some(3000));
+    }
+
+    #[test]
+    fn test_timestamp_with_invalid_utc_offset() {
+        let err = parse_message("<1>Jan 8 12:14:16 +25:00 1995 host - - -").unwrap_err();
+        assert_matches!(err.kind, ParseErr::InvalidUTCOffset);
+
+        let err = parse_message("<1>Jan 8 12:14:16 +02:60 1995 host - - -").unwrap_err();
+        assert_matches!(err.kind, ParseErr::InvalidUTCOffset);
+    }
+
+    #[test]
+    fn test_parsing_host_and_rest() {
+        let data = "host1[123]";
+        let res = parse_hostname(data, 255);
+        let (hostname, procid) = res.unwrap();
+        assert_eq!(hostname.unwrap(), "host1".to_owned());
+        assert_eq!(procid, "[123]".to_owned());
+    }
+
+    #[test]
+    fn test_complex() {
+        let msg = parse_message("<78>Jan  8 12:14:16 2017 host1[123] CROND some_message")
+            .expect("Should parse complex message");
+        assert_eq!(msg.facility, SyslogFacility::LOG_CRON);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_INFO);
+        assert_eq!(msg.priority, 78);
+        assert_eq!(msg.hostname, Some(String::from("host1")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(123)));
+        assert_eq!(msg.tag, Some(String::from("CROND")));
+        assert_eq!(msg.msg, String::from("some_message"));
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+    }
+
+    #[test]
+    fn test_parse_procid() {
+        assert_eq!(parse_procid("[123] tag").unwrap(), (Some("123"), " tag"));
+        assert_eq!(parse_procid("[abc] tag").unwrap(), (Some("abc"), " tag"));
+        assert_eq!(parse_procid("[-] tag").unwrap(), (None, " tag"));
+        assert_eq!(parse_procid(" tag").unwrap(), (None, " tag"));
+        assert_matches!(parse_procid("[] tag"), Err(ParseErr::MissingField("PROCID")));
+        assert_matches!(parse_procid("[123 tag"), Err(ParseErr::ExpectedTokenErr(']')));
+
+        let msg = parse_message("<78>Jan  8 12:14:16 host[123] tag: hi").unwrap();
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(123)));
+        assert_eq!(msg.tag, Some(String::from("tag")));
+
+        let msg = parse_message("<78>Jan  8 12:14:16 host tag: hi").unwrap();
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.tag, Some(String::from("tag")));
+
+        let msg = parse_message("<78>Jan  8 12:14:16 host[abc] tag: hi").unwrap();
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::Name(String::from("abc"))));
+        assert_eq!(msg.tag, Some(String::from("tag")));
+        assert_eq!(msg.msg, "hi");
+    }
+
+    #[test]
+    fn test_hostname_brackets() {
+        assert_eq!(parse_hostname("host1[123]", 255).unwrap(), (Some("host1"), "[123]"));
+        assert_eq!(parse_hostname("host1] x", 255).unwrap(), (Some("host1"), "] x"));
+        assert_eq!(parse_hostname("host1[", 255).unwrap(), (Some("host1"), "["));
+    }
+
+    #[test]
+    fn test_ipv6_hostname() {
+        assert_eq!(parse_hostname("[2001:db8::1] app", 255).unwrap(), (Some("2001:db8::1"), " app"));
+        assert_matches!(parse_hostname("[] app", 255), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_hostname("[2001:db8::1 app", 255), Err(ParseErr::ExpectedTokenErr(']')));
+
+        let msg = parse_message("<13>Feb 18 20:53:31 2018 [2001:db8::1] app: hi")
+            .expect("Should parse message with an IPv6 hostname");
+        assert_eq!(msg.hostname, Some(String::from("2001:db8::1")));
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1518987211));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "hi");
+    }
+
+    #[test]
+    fn test_missing_hostname() {
+        let msg = parse_message("<13>Feb 18 20:53:31 host app: hi").expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "hi");
+
+        let msg = parse_message("<13>Feb 18 20:53:31 app: hi").expect("Should parse message");
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "hi");
+
+        let msg = parse_message("<13>Feb 18 20:53:31 app[123]: hi").expect("Should parse message");
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(123)));
+        assert_eq!(msg.msg, "hi");
+
+        // Not a tag: a PID after the hostname, a port, and an IPv6 address
+        let msg = parse_message("<13>Feb 18 20:53:31 host[123] app hi").expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(123)));
+        let msg = parse_message("<13>Feb 18 20:53:31 host:514 app: hi").expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        let msg = parse_message("<13>Feb 18 20:53:31 fe80::1 app: hi").expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("fe80::1")));
+    }
+
+    #[test]
+    fn test_hostname_port() {
+        assert_eq!(parse_hostname_port("host1.example.com app", 255).unwrap(), ((Some("host1.example.com"), None), " app"));
+        assert_eq!(parse_hostname_port("host1.example.com:514 app", 255).unwrap(),
+                   ((Some("host1.example.com"), Some(514)), " app"));
+        assert_eq!(parse_hostname_port("[2001:db8::1]:514 app", 255).unwrap(), ((Some("2001:db8::1"), Some(514)), " app"));
+        assert_eq!(parse_hostname_port("2001:db8::1 app", 255).unwrap(), ((Some("2001:db8::1"), None), " app"));
+        assert_eq!(parse_hostname_port("host1:99999 app", 255).unwrap(), ((Some("host1:99999"), None), " app"));
+        assert_eq!(parse_hostname_port("host1:http app", 255).unwrap(), ((Some("host1:http"), None), " app"));
+        assert_eq!(parse_hostname_port("host1.example.com app", 5).unwrap(), ((None, None), " app"));
+        assert_eq!(parse_hostname_port("[2001:db8::1]:514 app", 5).unwrap(), ((None, None), " app"));
+        assert_eq!(parse_hostname_port("host1.example.com:514 app", 5).unwrap(), ((None, None), " app"));
+        assert_eq!(parse_hostname_port("host1 app", 0).unwrap(), ((None, None), " app"));
+
+        let msg = parse_message("<78>Jan 8 12:14:16 host1.example.com:514 CROND[123]: some_message")
+            .expect("Should parse message with a port");
+        assert_eq!(msg.hostname, Some(String::from("host1.example.com")));
+        assert_eq!(msg.port, Some(514));
+        assert_eq!(msg.tag, Some(String::from("CROND")));
+        assert_eq!(msg.msg, "some_message");
+
+        let msg = parse_message("<78>Jan 8 12:14:16 host1.example.com CROND[123]: some_message")
+            .expect("Should parse message without a port");
+        assert_eq!(msg.hostname, Some(String::from("host1.example.com")));
+        assert_eq!(msg.port, None);
+    }
+
+    #[test]
+    fn test_ipv6_hostname_with_zone_id() {
+        let msg = parse_message("<13>Feb 18 20:53:31 2018 [fe80::1%eth0] app: hi")
+            .expect("Should parse message with a zone ID in the hostname");
+        assert_eq!(msg.hostname, Some(String::from("fe80::1%eth0")));
+    }
+
+    #[test]
+    fn test_nilvalues() {
+        let cases = [
+            ("<78>Jan 8 12:14:16 2017 host1 123 CROND msg", Some("host1"), Some(ProcIdType::PID(123)), Some("CROND")),
+            ("<78>Jan 8 12:14:16 2017 - 123 CROND msg", None, Some(ProcIdType::PID(123)), Some("CROND")),
+            ("<78>Jan 8 12:14:16 2017 host1 - CROND msg", Some("host1"), None, Some("CROND")),
+            ("<78>Jan 8 12:14:16 2017 host1 123 - msg", Some("host1"), Some(ProcIdType::PID(123)), None),
+            ("<78>Jan 8 12:14:16 2017 - - CROND msg", None, None, Some("CROND")),
+            ("<78>Jan 8 12:14:16 2017 - 123 - msg", None, Some(ProcIdType::PID(123)), None),
+            ("<78>Jan 8 12:14:16 2017 host1 - - msg", Some("host1"), None, None),
+            ("<78>Jan 8 12:14:16 2017 - - - msg", None, None, None),
+        ];
+        for &(input, ref hostname, ref proc_id, ref tag) in cases.iter() {
+            let msg = parse_message(input).expect("Should parse message");
+            assert_eq!(msg.hostname.as_deref(), *hostname, "{}", input);
+            assert_eq!(msg.proc_id, *proc_id, "{}", input);
+            assert_eq!(msg.tag.as_deref(), *tag, "{}", input);
+            assert_eq!(msg.msg, "msg", "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_dash_within_token() {
+        let msg = parse_message("<78>Jan 8 12:14:16 2017 -host1 123 -CROND msg").expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("-host1")));
+        assert_eq!(msg.tag, Some(String::from("-CROND")));
+    }
+
+    #[test]
+    fn test_structured_data() {
+        let msg = parse_message(r#"<78>Jan 8 12:14:16 host1 123 CROND [meta sequenceId="29" x-group="37051387"][origin] some_message"#)
+            .expect("Should parse message with structured data");
+        assert_eq!(msg.structured_data.len(), 2);
+        assert_eq!(msg.structured_data[0].id, "meta");
+        assert_eq!(msg.structured_data[0].params(), vec![
+            (String::from("sequenceId"), String::from("29")),
+            (String::from("x-group"), String::from("37051387")),
+        ]);
+        assert_eq!(msg.structured_data[1].id, "origin");
+        assert!(msg.structured_data[1].params().is_empty());
+        assert_eq!(msg.msg, "some_message");
+    }
+
+    #[test]
+    fn test_no_structured_data() {
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 123 CROND some_message")
+            .expect("Should parse message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "some_message");
+    }
+
+    #[test]
+    fn test_structured_data_param_storage() {
+        let (elements, _) = parse_structured_data(r#"[few a="1" b="2"][many a="1" b="2" c="3" d="4" e="5"]"#)
+            .expect("Should parse structured data");
+        assert_eq!(elements[0].params().len(), 2);
+        assert_eq!(elements[1].params().len(), 5);
+        assert_eq!(elements[1].params()[4], (String::from("e"), String::from("5")));
+        #[cfg(feature = "smallvec")]
+        {
+            assert!(!elements[0].params.spilled());
+            assert!(elements[1].params.spilled());
+        }
+    }
+
+    #[test]
+    fn test_structured_data_escapes() {
+        let (elements, rest) = parse_structured_data(r#"[a b="q\"uo\\te" c="\x"] msg"#)
+            .expect("Should parse escaped values");
+        assert_eq!(elements[0].params(), vec![
+            (String::from("b"), String::from(r#"q"uo\te"#)),
+            (String::from("c"), String::from(r#"\x"#)),
+        ]);
+        assert_eq!(rest, " msg");
+    }
+
+    #[test]
+    fn test_parse_reader() {
+        let input = Cursor::new(&b"<78>Mar 15 14:16:22 host1 CROND: one\r\n\nnonsense\n<78>Mar 15 14:16:23 host1 CROND: caf\xe9"[..]);
+        let results: Vec<_> = parse_reader(input).collect();
+        assert_eq!(results.len(), 3);
+        assert_matches!(results[0], Ok(ref msg) if msg.msg == "one");
+        assert_matches!(results[1], Err(ReadError::Parse(ParseError { kind: ParseErr::ExpectedTokenErr('<'), .. })));
+        assert_matches!(results[2], Ok(ref msg) if msg.msg == "caf\u{fffd}");
+
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+        }
+        let mut results = parse_reader(BufReader::new(FailingReader));
+        assert_matches!(results.next(), Some(Err(ReadError::Io(ref e))) if e.kind() == io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_parse_concatenated() {
+        let results = parse_concatenated("<13>Mar 15 14:16:22 host1 app: one<14>Mar 15 14:16:23 host2 app[12]: two");
+        assert_eq!(results.len(), 2);
+        let first = results[0].as_ref().expect("Should parse first message");
+        assert_eq!(first.hostname, Some(String::from("host1")));
+        assert_eq!(first.msg, "one");
+        let second = results[1].as_ref().expect("Should parse second message");
+        assert_eq!(second.severity, SyslogSeverity::SEV_INFO);
+        assert_eq!(second.hostname, Some(String::from("host2")));
+        assert_eq!(second.proc_id, Some(ProcIdType::PID(12)));
+        assert_eq!(second.msg, "two");
+
+        // `<` not followed by a PRI stays in the body
+        let results = parse_concatenated("<13>Mar 15 14:16:22 host1 app: a <b> <1234> c");
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().expect("Should parse message").msg, "a <b> <1234> c");
+
+        let results = parse_concatenated("<13>Mar 15 14:16:22 host1 app: one<14>Mxr");
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_matches!(results[1], Err(ParseError { kind: ParseErr::MonthConversionErr(_), offset: 38, context: Some("timestamp") }));
+    }
+
+    #[test]
+    fn test_param_value_borrows_unless_escaped() {
+        let (value, rest) = parse_param_value(r#""plain" x"#).expect("Should parse value");
+        assert_matches!(value, Cow::Borrowed("plain"));
+        assert_eq!(rest, " x");
+        let (value, rest) = parse_param_value(r#""q\"uo\\te\x"]"#).expect("Should parse value");
+        assert_matches!(value, Cow::Owned(ref v) if v == r#"q"uo\te\x"#);
+        assert_eq!(rest, "]");
+        assert_matches!(parse_param_value(r#""unterminated\""#), Err(ParseErr::UnexpectedEndOfInput));
+    }
+
+    #[test]
+    fn test_param_value_escapes() {
+        let msg = parse_message(r#"<78>Jan 8 12:14:16 host1 CROND: [meta a="a\"b" b="a\\b" c="a\]b" d="a\b"][x y="]"] msg"#)
+            .expect("Should parse message");
+        assert_eq!(msg.structured_data.len(), 2);
+        assert_eq!(msg.structured_data[0].params(), vec![
+            (String::from("a"), String::from(r#"a"b"#)),
+            (String::from("b"), String::from(r#"a\b"#)),
+            (String::from("c"), String::from("a]b")),
+            (String::from("d"), String::from(r#"a\b"#)),
+        ]);
+        // Unescaped, a `]` inside a value is still part of it, and the one after it ends the element
+        assert_eq!(msg.structured_data[1].params(), vec![(String::from("y"), String::from("]"))]);
+        assert_eq!(msg.msg, "msg");
+    }
+
+    #[test]
+    fn test_invalid_structured_data_is_message() {
+        let msg = parse_message(r#"<78>Jan 8 12:14:16 host1 123 CROND [meta x="unterminated] msg"#)
+            .expect("Should parse message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, r#"[meta x="unterminated] msg"#);
+    }
+
+    #[test]
+    fn test_other_message() {
+        let msg_text = include_str!("../testdata/long_message.txt");
+        let msg = parse_message(msg_text).expect("should parse as text");
+        assert_eq!(msg.hostname, Some(String::from("batch6sj")));
+        assert_eq!(msg.structured_data.len(), 2);
+        assert_eq!(msg.structured_data[0].id, "meta");
+        assert_eq!(msg.structured_data[0].params(), vec![
+            (String::from("sequenceId"), String::from("21881798")),
+            (String::from("x-group"), String::from("37051387")),
+        ]);
+        assert_eq!(msg.structured_data[1].id, "origin");
+        assert!(msg.msg.starts_with("metascutellar conversationalist"));
+
+        let encoded = serde_json::to_string(&msg).expect("Should encode to JSON");
+        assert!(encoded.contains(r#""structured_data":[{"id":"meta","params":[["sequenceId","21881798"],["x-group","37051387"]]},{"id":"origin","params":[["x-service","tracking"]]}]"#));
+        assert!(encoded.contains(r#""msg":"metascutellar conversationalist"#));
+    }
+
+    #[test]
+    fn test_bracketed_text_is_not_structured_data() {
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 app: [INFO] started").expect("Should parse message");
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "[INFO] started");
+
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 app: - [x] done").expect("Should parse message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "- [x] done");
+
+        // An `@`-qualified SD-ID is Structured Data even without params
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 app: - [origin@32473] done").expect("Should parse message");
+        assert_eq!(msg.structured_data.len(), 1);
+        assert_eq!(msg.structured_data[0].id, "origin@32473");
+        assert_eq!(msg.msg, "done");
+    }
+
+    #[test]
+    fn test_structured_data_in_tag_position() {
+        let msg = parse_message(r#"<190>Jan 8 12:14:16 batch6sj [meta sequenceId="21881798"][origin x-service="tracking"] some text"#)
+            .expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("batch6sj")));
+        assert_eq!(msg.tag, None);
+        assert_eq!(msg.structured_data.len(), 2);
+        assert_eq!(msg.structured_data[0].id, "meta");
+        assert_eq!(msg.structured_data[1].id, "origin");
+        assert_eq!(msg.msg, "some text");
+
+        // A bracketed word is a tag, as it always was
+        let msg = parse_message("<190>Jan 8 12:14:16 host [INFO] msg").expect("Should parse message");
+        assert_eq!(msg.tag, Some(String::from("[INFO]")));
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "msg");
+    }
+
+    #[test]
+    fn test_parse_num_digit_limits() {
+        assert_eq!(parse_num("12345", 4, 4).expect("Should parse number"), (1234, "5"));
+        assert_eq!(parse_num("1234 ", 4, 4).expect("Should parse number"), (1234, " "));
+        assert_eq!(parse_num("1234", 4, 4).expect("Should parse number"), (1234, ""));
+        assert_eq!(parse_num("12:", 1, 2).expect("Should parse number"), (12, ":"));
+        assert_matches!(parse_num("123", 4, 4), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_num("123 ", 4, 4), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_num("x", 1, 2), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_num("", 1, 2), Err(ParseErr::UnexpectedEndOfInput));
+    }
+
+    #[test]
+    fn test_parse_num_space_padded() {
+        let lenient = ParseOptions::default();
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        assert_eq!(parse_num_opts(" 8", 1, 2, &lenient).expect("Should skip the space"), (8, ""));
+        assert_eq!(parse_num_opts(" 4:16", 2, 2, &lenient).expect("Should skip the space"), (4, ":16"));
+        assert_eq!(parse_num_opts("18", 1, 2, &lenient).expect("Should parse number"), (18, ""));
+        assert_matches!(parse_num_opts(" 8", 1, 2, &strict), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_num_opts("  8", 1, 2, &lenient), Err(ParseErr::TooFewDigits));
+        assert_matches!(parse_num(" 8", 1, 2), Err(ParseErr::TooFewDigits));
+
+        let msg = parse_message("<78>Jan  8 12: 4: 6 2017 host1 CROND: msg").expect("Should parse padded time");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877046));
+        assert_eq!(msg.hostname, Some(String::from("host1")));
+        assert!(parse_message_opts("<78>Jan  8 12: 4: 6 host1 CROND: msg", strict).is_err());
+    }
+
+    #[test]
+    fn test_month_case_insensitive() {
+        assert_eq!(parse_month("Jan 8").expect("Should parse month"), (1, " 8"));
+        assert_eq!(parse_month("JAN 8").expect("Should parse month"), (1, " 8"));
+        assert_eq!(parse_month("jan 8").expect("Should parse month"), (1, " 8"));
+        assert_matches!(parse_month("Jab 8"), Err(ParseErr::MonthConversionErr(ref m)) if m == "Jab");
+        assert_matches!(parse_month("JAB 8"), Err(ParseErr::MonthConversionErr(ref m)) if m == "JAB");
+
+        let msg = parse_message("<78>DEC 8 12:14:16 2017 host1 CROND[123]: some_message")
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1512735256));
+    }
+
+    #[test]
+    fn test_comma_after_day() {
+        let msg = parse_message("<78>Jan 8, 12:14:16 2017 host1 CROND[123]: some_message")
+            .expect("Should parse message");
+        let expected = parse_message("<78>Jan 8 12:14:16 2017 host1 CROND[123]: some_message")
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+        assert_eq!(msg.timestamp, expected.timestamp);
+        assert_eq!(msg.hostname, Some(String::from("host1")));
+
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        assert_matches!(parse_message_opts("<78>Jan 08, 12:14:16 host1 CROND[123]: some_message", opts),
+                        Err(ParseError { kind: ParseErr::ExpectedTokenErr(' '), .. }));
+    }
+
+    #[test]
+    fn test_iso8601_timestamp() {
+        let msg = parse_message("<34>2003-10-11T22:14:15Z mymachine su: 'su root' failed")
+            .expect("Should parse message");
+        assert_eq!(msg.version, 0);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1065910455));
+        assert_eq!(msg.timestamp_nanos, None);
+        assert_eq!(msg.hostname, Some(String::from("mymachine")));
+        assert_eq!(msg.tag, Some(String::from("su")));
+        assert_eq!(msg.msg, "'su root' failed");
+
+        let msg = parse_message("<34>2003-10-11T15:14:15-07:00 mymachine su: 'su root' failed")
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1065910455));
+
+        let msg = parse_message("<34>2003-10-11T22:14:15.003Z mymachine su: 'su root' failed")
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1065910455));
+        assert_eq!(msg.timestamp_nanos, Some(3000000));
+
+        // The usual form still works alongside it
+        let msg = parse_message("<34>Oct 11 22:14:15 2003 mymachine su: 'su root' failed")
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1065910455));
+
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        assert!(parse_message_opts("<34>2003-10-11T22:14:15Z mymachine su: hi", strict).is_err());
+    }
+
+    #[test]
+    fn test_owned() {
+        let input = String::from("<78>Mar 15 14:16:22 host1 CROND[10391]: some_message");
+        let expected = parse_message(&input).expect("Should parse message");
+        let msg = parse_message_owned(input).expect("Should parse owned message");
+        assert_eq!(msg, expected);
+        assert_eq!(msg.tag, Some(String::from("CROND")));
+
+        assert!(parse_message_owned(String::from("<78>")).is_err());
+    }
+
+    #[test]
+    fn test_fields() {
+        use super::fields;
+
+        assert_eq!(fields::parse_month("Feb 18").unwrap(), (2, " 18"));
+        assert_eq!(fields::parse_num("18 20", 1, 2).unwrap(), (18, " 20"));
+        let ((timestamp, nanos), rest) = fields::parse_timestamp("Jan  8 12:14:16.5 2017 host1", &ParseOptions::default())
+            .expect("Should parse timestamp");
+        assert_eq!(timestamp.map(|ts| ts.secs()), Some(1483877656));
+        assert_eq!(nanos, Some(500000000));
+        assert_eq!(rest, " host1");
+        assert_eq!(fields::parse_hostname("[2001:db8::1] app").unwrap(), (Some("2001:db8::1"), " app"));
+        assert_eq!(fields::parse_tag("sshd[123]: hi").unwrap(), ((Some("sshd"), Some("123")), " hi"));
+        assert_matches!(fields::parse_tag(" hi"), Err(ParseErr::MissingField("TAG")));
+        let (elements, rest) = fields::parse_structured_data("[meta a=\"1\"] hi").unwrap();
+        assert_eq!(elements[0].id, "meta");
+        assert_eq!(rest, " hi");
+    }
+
+    #[test]
+    fn test_parse_multiline() {
+        let input = "<11>Mar 15 14:16:22 host1 java[12]: Exception in thread \"main\" java.lang.NullPointerException\r\n\
+                     \tat com.example.Main.main(Main.java:5)\n\
+                     <14>Mar 15 14:16:23 host1 java[12]: recovered\n\
+                     <999>Mar 15 14:16:24 host1 java[12]: bad\n\
+                     \tat dropped\n";
+        let results = parse_multiline(input);
+        assert_eq!(results.len(), 3);
+        let first = results[0].as_ref().expect("Should parse first message");
+        assert_eq!(first.tag, Some(String::from("java")));
+        assert_eq!(first.msg, "Exception in thread \"main\" java.lang.NullPointerException\n\
+                               \tat com.example.Main.main(Main.java:5)");
+        assert_eq!(results[1].as_ref().expect("Should parse second message").msg, "recovered");
+        let offset = input.find("<999>").unwrap();
+        assert_matches!(results[2], Err(ParseError { kind: ParseErr::BadFacilityInPri(999), .. }));
+        assert_eq!(results[2].as_ref().unwrap_err().offset, offset + 5);
+
+        let results = parse_multiline("\tat nothing\n<14>Mar 15 14:16:23 host1 java[12]: ok");
+        assert_eq!(results.len(), 2);
+        assert_matches!(results[0], Err(ParseError { kind: ParseErr::ExpectedTokenErr('<'), offset: 0, .. }));
+        assert!(results[1].is_ok());
+    }
+
+    #[test]
+    fn test_is_valid_message() {
+        let inputs = [
+            "<78>Jan  8 12:14:16 host1 CROND[123]: some_message",
+            "<1>Jan 8 12:14:16 1995 host - - - -",
+            "<34>1 2003-10-11T22:14:15Z host su 1234 - - 'su root' failed",
+            "<78>Jan  8 12:14:16 host1 [meta seq=\"1\"] msg",
+            "",
+            "nonsense",
+            "<78Jan 8 12:14:16 host1 CROND msg",
+            "<78>Jan 8 12:14",
+            "<999>Mar 15 14:16:25 host1 CROND[4]: fourth",
+            "<1>Jan 8 12:14:16 +02:60 1995 host - - -",
+        ];
+        for input in &inputs {
+            assert_eq!(is_valid_message(input), parse_message(input).is_ok(), "{}", input);
+        }
+        assert!(is_valid_message(inputs[0]));
+        assert!(!is_valid_message(inputs[5]));
+    }
 
-        assert_eq!(msg.timestamp, Some(tm.to_utc().to_timespec().sec));
-        assert_eq!(msg.hostname, Some("host".into()));
+    #[test]
+    fn test_parse_into() {
+        let mut msg = parse_message("<78>Jan  8 12:14:16 host1 CROND[123]: [meta seq=\"1\"] first").unwrap();
+        let hostname_ptr = msg.hostname.as_ref().map(|h| h.as_ptr());
+
+        parse_into("<13>Jan  8 12:14:17 host2 sshd: second", &mut msg).unwrap();
+        assert_eq!(msg, parse_message("<13>Jan  8 12:14:17 host2 sshd: second").unwrap());
+        assert_eq!(msg.hostname.as_ref().map(|h| h.as_ptr()), hostname_ptr);
+
+        parse_into("<13>1 2003-10-11T22:14:15Z - - - - - third", &mut msg).unwrap();
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, None);
+        assert_eq!(msg.msg, "third");
+
+        let before = msg.clone();
+        assert!(parse_into("nonsense", &mut msg).is_err());
+        assert_eq!(msg, before);
     }
 
     #[test]
-    fn test_timestamp_with_year_in_message() {
-        let msg = parse_message("<1>Jan 8 12:14:16 1995 host - - - -")
-            .expect("Should parse empty message");
-        assert_eq!(msg.timestamp, Some(789567256));
+    fn test_empty_input() {
+        for input in &["", "   ", "\n", "\u{feff}\r\n"] {
+            let err = parse_message(input).unwrap_err();
+            assert_matches!(err, ParseError { kind: ParseErr::EmptyInput, context: None, .. });
+        }
+        let err = parse_message("<").unwrap_err();
+        assert_matches!(err.kind, ParseErr::UnexpectedEndOfInput);
     }
 
     #[test]
-    fn test_parsing_host_and_rest() {
-        let data = "host1[123]";
-        let res = parse_hostname(&data);
-        let (hostname, procid) = res.unwrap();
-        assert_eq!(hostname.unwrap(), "host1".to_owned());
-        assert_eq!(procid, "[123]".to_owned());
+    fn test_error_context() {
+        let err = parse_message("<78>Jan 8 12:").unwrap_err();
+        assert_matches!(err, ParseError { kind: ParseErr::UnexpectedEndOfInput, offset: 4, context: Some("timestamp") });
+        assert_eq!(err.to_string(), "UnexpectedEndOfInput while parsing timestamp at byte 4");
+
+        // Framed input keeps the context, with the offset counting the frame's length prefix
+        let err = parse_framed(b"13 <78>Jan 8 12:").unwrap_err();
+        assert_matches!(err, ParseError { kind: ParseErr::UnexpectedEndOfInput, offset: 7, context: Some("timestamp") });
+
+        let err = parse_message("<78").unwrap_err();
+        assert_eq!(err.context(), Some("PRI"));
+        assert_eq!(err.to_string(), "UnexpectedEndOfInput while parsing PRI at byte 3");
+
+        let kind = ParseErr::TooFewDigits.context("hostname").context("message");
+        assert_eq!(kind.to_string(), "TooFewDigits while parsing hostname");
     }
 
     #[test]
-    fn test_complex() {
-        let msg = parse_message("<78>Jan  8 12:14:16 2017 host1[123] CROND some_message")
-            .expect("Should parse complex message");
-        assert_eq!(msg.facility, SyslogFacility::LOG_CRON);
+    fn test_trim_trailing() {
+        for input in &["<78>Jan 8 12:14:16 host1 CROND: some_message\n",
+                       "<78>Jan 8 12:14:16 host1 CROND: some_message\r\n",
+                       "<78>Jan 8 12:14:16 host1 CROND: some_message\0"] {
+            let msg = parse_message(input).expect("Should parse message");
+            assert_eq!(msg.msg, "some_message");
+            assert_eq!(parse_message_ref(input).expect("Should parse message").msg, "some_message");
+        }
+        // Only one is dropped
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 CROND: some_message\n\n").expect("Should parse message");
+        assert_eq!(msg.msg, "some_message\n");
+        let msg = parse_message("<78>1 2003-10-11T22:14:15.003Z host1 app - - - some_message\n")
+            .expect("Should parse message");
+        assert_eq!(msg.msg, "some_message");
+
+        let opts = ParseOptions { trim_trailing: false, ..Default::default() };
+        let msg = parse_message_opts("<78>Jan 8 12:14:16 host1 CROND: some_message\r\n", opts)
+            .expect("Should parse message");
+        assert_eq!(msg.msg, "some_message\r\n");
+    }
+
+    #[test]
+    fn test_keep_raw() {
+        let input = "\u{feff}<78>Mar 15 14:16:22 host1 CROND[10391]: some\u{1b}message";
+        let msg = parse_message_keep_raw(input).expect("Should parse message");
+        assert_eq!(msg.raw, Some(String::from(input)));
+        assert_eq!(msg.tag, Some(String::from("CROND")));
+
+        let opts = ParseOptions { keep_raw: true, sanitize_msg: true, ..Default::default() };
+        let msg = parse_message_opts(input, opts).expect("Should parse message");
+        assert_eq!(msg.msg, "somemessage");
+        assert_eq!(msg.raw, Some(String::from(input)));
+
+        let msg = parse_message(input).expect("Should parse message");
+        assert_eq!(msg.raw, None);
+        assert!(!serde_json::to_string(&msg).expect("Should encode to JSON").contains("\"raw\""));
+
+        // Bytes after invalid UTF-8 are kept too
+        let mut decoder = Rfc3164Decoder::with_options(ParseOptions { keep_raw: true, ..Default::default() });
+        let mut buf = b"<78>Mar 15 14:16:22 host1 CROND: caf\xe9 au lait\n".to_vec();
+        let msg = decoder.decode(&mut buf).expect("Should parse message").expect("Should have a message");
+        assert_eq!(msg.msg, "caf\u{fffd} au lait");
+        assert_eq!(msg.raw, Some(String::from("<78>Mar 15 14:16:22 host1 CROND: caf\u{fffd} au lait")));
+    }
+
+    #[test]
+    fn test_invalid_date_fields() {
+        assert_matches!(parse_message("<78>Jan 40 12:14:16 host1 CROND: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), offset: 4, context: Some("timestamp") }));
+        assert_matches!(parse_message("<78>Jan 8 99:00:00 host1 CROND: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("hour"), .. }));
+        assert_matches!(parse_message("<78>Jan 8 12:61:00 host1 CROND: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("minute"), .. }));
+        assert_matches!(parse_message("<78>Jan 8 12:00:61 host1 CROND: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("second"), .. }));
+        assert_matches!(parse_message("<34>1 2003-13-11T22:14:15Z host app - - - msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("month"), .. }));
+        assert_matches!(parse_message("<13>Feb 30 20:53:31 2017 host app: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), offset: 4, context: Some("timestamp") }));
+        assert_matches!(parse_message("<13>Apr 31 20:53:31 2017 host app: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+        assert_matches!(parse_message("<13>Feb 29 20:53:31 2017 host app: msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+        assert_matches!(parse_message("<34>1 2003-02-30T22:14:15Z host app - - - msg"),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+        let msg = parse_message("<13>Feb 29 20:53:31 2016 host app: msg").expect("Should parse leap day");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1456779211));
+        let msg = parse_message_ref_in_year("<13>Feb 29 20:53:31 host app: msg", 2016).expect("Should parse leap day");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1456779211));
+        assert_matches!(parse_message_ref_in_year("<13>Feb 29 20:53:31 host app: msg", 2017),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        assert_matches!(parse_message_opts("<78>Jan 40 12:14:16 host1 CROND: msg", strict.clone()),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+        assert_matches!(parse_message_opts("<78>Feb 30 12:14:16 host1 CROND: msg", strict),
+                        Err(ParseError { kind: ParseErr::InvalidDateField("day"), .. }));
+
+        // A leap second is fine
+        let msg = parse_message("<78>Dec 31 23:59:60 2016 host1 CROND: msg").expect("Should parse leap second");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483228800));
+    }
+
+    #[test]
+    fn test_default_pri() {
+        let opts = ParseOptions {
+            default_facility: Some(SyslogFacility::LOG_LOCAL0),
+            default_severity: Some(SyslogSeverity::SEV_INFO),
+            ..Default::default()
+        };
+        let msg = parse_message_opts("<4096>Jan 8 12:14:16 host1 CROND: msg", opts.clone())
+            .expect("Should recover from a bad PRI");
+        assert!(msg.pri_recovered);
+        assert_eq!(msg.priority, 4096);
+        assert_eq!(msg.facility, SyslogFacility::LOG_LOCAL0);
         assert_eq!(msg.severity, SyslogSeverity::SEV_INFO);
         assert_eq!(msg.hostname, Some(String::from("host1")));
+        assert_eq!(msg.msg, "msg");
+
+        let msg = parse_message_opts("<999>1 2003-10-11T22:14:15Z host app - - - msg", opts.clone())
+            .expect("Should recover from a bad PRI");
+        assert!(msg.pri_recovered);
+        assert_eq!(msg.version, 1);
+
+        let msg = parse_message_opts("<13>Jan 8 12:14:16 host1 CROND: msg", opts.clone())
+            .expect("Should parse message");
+        assert!(!msg.pri_recovered);
+        assert_eq!(msg.facility, SyslogFacility::LOG_USER);
+
+        // Malformed PRIs are still errors
+        assert!(parse_message_opts("<4x>Jan 8 12:14:16 host1 CROND: msg", opts.clone()).is_err());
+        assert!(parse_message_opts("<>Jan 8 12:14:16 host1 CROND: msg", opts.clone()).is_err());
+
+        let strict = ParseOptions { strict: true, ..opts };
+        assert!(parse_message_opts("<4096>Jan  8 12:14:16 host1 CROND: msg", strict).is_err());
+        assert!(parse_message("<4096>Jan 8 12:14:16 host1 CROND: msg").is_err());
+    }
+
+    #[test]
+    fn test_rfc3164_has_version_zero() {
+        let msg = parse_message("<78>Jan 8 12:14:16 2017 host1 123 CROND some_message")
+            .expect("Should parse message");
+        assert_eq!(msg.version, 0);
+    }
+
+    #[test]
+    fn test_rfc5424() {
+        let msg = parse_message("<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed for lonvick on /dev/pts/8")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.facility, SyslogFacility::LOG_AUTH);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_CRIT);
+        assert_eq!(msg.version, 1);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1065910455));
+        assert_eq!(msg.hostname, Some(String::from("mymachine.example.com")));
+        assert_eq!(msg.tag, Some(String::from("su")));
+        assert_eq!(msg.proc_id, None);
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "'su root' failed for lonvick on /dev/pts/8");
+    }
+
+    #[test]
+    fn test_rfc5424_app_name_is_tag() {
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host su 1234 - - 'su root' failed")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.tag, Some(String::from("su")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(1234)));
+        assert_eq!(msg.msg, "'su root' failed");
+
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host - - - - 'su root' failed")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.tag, None);
+        assert_eq!(msg.proc_id, None);
+    }
+
+    #[test]
+    fn test_tag_excludes_colon() {
+        let msg = parse_message("<78>Jan  8 12:14:16 host1 nginx: GET /").unwrap();
+        assert_eq!(msg.tag, Some(String::from("nginx")));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.msg, "GET /");
+
+        let msg = parse_message("<78>Jan  8 12:14:16 host1 nginx[12]: GET /").unwrap();
+        assert_eq!(msg.tag, Some(String::from("nginx")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(12)));
+        assert_eq!(msg.msg, "GET /");
+
+        let msg = parse_message("<78>Jan  8 12:14:16 host1 nginx GET /").unwrap();
+        assert_eq!(msg.tag, Some(String::from("nginx")));
+        assert_eq!(msg.msg, "GET /");
+
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        let msg = parse_message_opts("<78>Jan  8 12:14:16 host1 nginx: GET /", strict).unwrap();
+        assert_eq!(msg.tag, Some(String::from("nginx")));
+        assert_eq!(msg.msg, "GET /");
+    }
+
+    #[test]
+    fn test_rfc5424_with_offset_and_pid() {
+        let msg = parse_message("<165>1 2003-08-24T05:14:15.000003-07:00 192.0.2.1 myproc 8710 - - %% It's time to make the do-nuts.")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.version, 1);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1061727255));
+        assert_eq!(msg.hostname, Some(String::from("192.0.2.1")));
+        assert_eq!(msg.tag, Some(String::from("myproc")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(8710)));
+        assert_eq!(msg.msg, "%% It's time to make the do-nuts.");
+    }
+
+    #[test]
+    fn test_rfc5424_with_structured_data() {
+        let msg = parse_message(r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="Application"] An application event log entry..."#)
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.tag, Some(String::from("evntslog")));
+        assert_eq!(msg.structured_data.len(), 1);
+        assert_eq!(msg.structured_data[0].id, "exampleSDID@32473");
+        assert_eq!(msg.structured_data[0].params(), vec![
+            (String::from("iut"), String::from("3")),
+            (String::from("eventSource"), String::from("Application")),
+        ]);
+        assert_eq!(msg.msg, "An application event log entry...");
+    }
+
+    #[test]
+    fn test_rfc5424_msg_id() {
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 - msg")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.msg_id, Some(String::from("ID47")));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "msg");
+        let encoded = serde_json::to_string(&msg).expect("Should encode to JSON");
+        assert!(encoded.contains(r#""msg_id":"ID47""#));
+
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 - - msg")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.msg_id, None);
+
+        let msg = parse_message("<34>Oct 11 22:14:15 host app[1234]: ID47 msg").expect("Should parse message");
+        assert_eq!(msg.msg_id, None);
+
+        let encoded = serde_json::to_string(&msg).expect("Should encode to JSON");
+        assert!(encoded.contains(r#""msg_id":null"#));
+    }
+
+    #[test]
+    fn test_rfc5424_nil_structured_data() {
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 - msg")
+            .expect("Should parse RFC5424 message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(1234)));
+        assert_eq!(msg.msg, "msg");
+
+        // The `-` is the whole SD, so nothing after it is Structured Data
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 - [id] msg")
+            .expect("Should parse RFC5424 message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "[id] msg");
+
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 - - msg")
+            .expect("Should parse RFC5424 message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "- msg");
+
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 -")
+            .expect("Should parse RFC5424 message");
+        assert!(msg.structured_data.is_empty());
+        assert_eq!(msg.msg, "");
+
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host app 1234 ID47 [id] msg")
+            .expect("Should parse RFC5424 message");
+        assert_eq!(msg.structured_data.len(), 1);
+        assert_eq!(msg.structured_data[0].id, "id");
+        assert!(msg.structured_data[0].params().is_empty());
+        assert_eq!(msg.msg, "msg");
+    }
+
+    #[test]
+    fn test_rfc5424_bad_timestamp() {
+        let msg = parse_message("<34>1 Oct 11 22:14:15 mymachine.example.com su - ID47 - msg");
+        assert!(msg.is_err());
+    }
+
+    #[test]
+    fn test_strict() {
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        let msg = parse_message_opts("<13>Feb  5 17:32:18 host1 sshd[123]: connected", opts.clone())
+            .expect("Should parse strict message");
+        assert_eq!(msg.hostname, Some(String::from("host1")));
+        assert_eq!(msg.tag, Some(String::from("sshd")));
         assert_eq!(msg.proc_id, Some(ProcIdType::PID(123)));
-        assert_eq!(msg.msg, String::from("CROND some_message"));
-        assert_eq!(msg.timestamp, Some(1483877656));
+        assert_eq!(msg.msg, "connected");
+
+        let msg = parse_message_opts("<13>Feb 15 17:32:18 host1 sshd: connected", opts)
+            .expect("Should parse strict message");
+        assert_eq!(msg.tag, Some(String::from("sshd")));
+        assert_eq!(msg.proc_id, None);
     }
 
     #[test]
-    fn test_other_message() {
-        let msg_text = r#"<190>Jan 8 12:14:16 batch6sj - - - [meta sequenceId="21881798" x-group="37051387"][origin x-service="tracking"] metascutellar conversationalist nephralgic exogenetic graphy streng outtaken acouasm amateurism prenotice Lyonese bedull antigrammatical diosphenol gastriloquial bayoneteer sweetener naggy roughhouser dighter addend sulphacid uneffectless ferroprussiate reveal Mazdaist plaudite Australasian distributival wiseman rumness Seidel topazine shahdom sinsion mesmerically pinguedinous ophthalmotonometer scuppler wound eciliate expectedly carriwitchet dictatorialism bindweb pyelitic idic atule kokoon poultryproof rusticial seedlip nitrosate splenadenoma holobenthic uneternal Phocaean epigenic doubtlessly indirection torticollar robomb adoptedly outspeak wappenschawing talalgia Goop domitic savola unstrafed carded unmagnified mythologically orchester obliteration imperialine undisobeyed galvanoplastical cycloplegia quinquennia foremean umbonal marcgraviaceous happenstance theoretical necropoles wayworn Igbira pseudoangelic raising unfrounced lamasary centaurial Japanolatry microlepidoptera"#;
-        parse_message(msg_text).expect("should parse as text");
+    fn test_strict_rejects_leniency() {
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        // Double space between fields
+        assert_matches!(parse_message_opts("<13>Feb 15 17:32:18  host1 sshd: connected", opts.clone()),
+                        Err(ParseError { kind: ParseErr::TooFewDigits, .. }));
+        // Single-digit day without padding
+        assert_matches!(parse_message_opts("<13>Feb 5 17:32:18 host1 sshd: connected", opts.clone()),
+                        Err(ParseError { kind: ParseErr::TooFewDigits, .. }));
+        // Missing hostname
+        assert_matches!(parse_message_opts("<13>Feb 15 17:32:18 - sshd: connected", opts.clone()),
+                        Err(ParseError { kind: ParseErr::MissingField("HOSTNAME"), .. }));
+        // Tag longer than 32 characters
+        assert_matches!(parse_message_opts("<13>Feb 15 17:32:18 host1 abcdefghijklmnopqrstuvwxyz0123456789: connected", opts),
+                        Err(ParseError { kind: ParseErr::TagTooLong(36), .. }));
+        // Lenient by default
+        assert!(parse_message("<13>Feb 5 17:32:18 host1 sshd: connected").is_ok());
+    }
+
+    #[test]
+    fn test_tag_length_limit() {
+        let tag = "a".repeat(33);
+        let input = format!("<13>Feb 15 17:32:18 host1 {}: connected", tag);
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        assert_matches!(parse_message_opts(&input[..], opts),
+                        Err(ParseError { kind: ParseErr::TagTooLong(33), offset: 26, context: Some("tag") }));
+
+        let msg = parse_message(&input[..]).expect("Should parse message");
+        assert_eq!(msg.tag, Some(tag.clone()));
+        assert_eq!(msg.msg, "connected");
+
+        let input = format!("<13>Feb 15 17:32:18 host1 {}: connected", &tag[..32]);
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        assert!(parse_message_opts(&input[..], opts).is_ok());
+    }
+
+    #[test]
+    fn test_hostname_length_limit() {
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        let hostname = "h".repeat(256);
+        let input = format!("<13>Feb 15 17:32:18 {} app: connected", &hostname[..255]);
+        let msg = parse_message_opts(&input[..], strict.clone()).expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from(&hostname[..255])));
+
+        let input = format!("<13>Feb 15 17:32:18 {} app: connected", hostname);
+        assert_matches!(parse_message_opts(&input[..], strict.clone()),
+                        Err(ParseError { kind: ParseErr::HostnameTooLong(256), offset: 20, context: Some("hostname") }));
+
+        let input = "<13>Feb 15 17:32:18 host1.example.com app: connected";
+        let opts = ParseOptions { max_hostname_len: 5, ..strict };
+        assert_matches!(parse_message_opts(input, opts), Err(ParseError { kind: ParseErr::HostnameTooLong(17), .. }));
+        let opts = ParseOptions { max_hostname_len: 17, ..Default::default() };
+        assert!(parse_message_opts(input, opts).is_ok());
+
+        // Leniently, the over-long hostname is skipped rather than split into a hostname and tag
+        let opts = ParseOptions { max_hostname_len: 5, ..Default::default() };
+        let msg = parse_message_opts(input, opts.clone()).expect("Should parse message");
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "connected");
+        let msg = parse_message_opts("<13>Feb 15 17:32:18 [2001:db8::1]:514 app: connected", opts)
+            .expect("Should parse message");
+        assert_eq!((msg.hostname, msg.port), (None, None));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "connected");
+        let opts = ParseOptions { max_hostname_len: 0, ..Default::default() };
+        let msg = parse_message_opts(input, opts).expect("Should parse message");
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, Some(String::from("app")));
+    }
+
+    #[test]
+    fn test_partial() {
+        let input = "<78>Jan 8 12:14:16 host1 123 CROND first\n<78>Jan 8 12:14:17 host1 123 CROND second";
+        let (msg, rest) = parse_message_partial(input).expect("Should parse first message");
+        assert_eq!(msg.msg, "first");
+        assert_eq!(rest, "<78>Jan 8 12:14:17 host1 123 CROND second");
+
+        let (msg, rest) = parse_message_partial(rest).expect("Should parse second message");
+        assert_eq!(msg.msg, "second");
+        assert_eq!(rest, "");
+
+        let (_, rest) = parse_message_partial("<78>Jan 8 12:14:16 host1 123 CROND first\n<78>")
+            .expect("Should parse first message");
+        assert_eq!(rest, "<78>");
+        assert_matches!(parse_message_partial("<78>Jxn\n"), Err(ParseError { offset: 4, .. }));
+    }
+
+    #[test]
+    fn test_sanitize_msg() {
+        let input = "<78>Jan 8 12:14:16 host1 123 CROND some\u{0}\tmessage \u{1b}[31mred";
+        let msg = parse_message(input).expect("Should parse message");
+        assert_eq!(msg.msg, "some\u{0}\tmessage \u{1b}[31mred");
+
+        let opts = ParseOptions { sanitize_msg: true, ..Default::default() };
+        let msg = parse_message_opts(input, opts).expect("Should parse message");
+        assert_eq!(msg.msg, "some\tmessage [31mred");
+    }
+
+    #[test]
+    fn test_missing_pri() {
+        let input = "Feb 18 20:53:31 2018 host 123 app msg";
+        assert_matches!(parse_message(input), Err(ParseError { kind: ParseErr::ExpectedTokenErr('<'), .. }));
+
+        let opts = ParseOptions { allow_missing_pri: true, ..Default::default() };
+        let msg = parse_message_opts(input, opts.clone()).expect("Should parse message without PRI");
+        assert_eq!(msg.facility, SyslogFacility::LOG_USER);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_NOTICE);
+        assert_eq!(msg.priority, MISSING_PRIORITY);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1518987211));
+        assert_eq!(msg.hostname, Some(String::from("host")));
+
+        let msg = parse_message_opts("<78>Feb 18 20:53:31 2018 host 123 app msg", opts)
+            .expect("Should parse message with PRI");
+        assert_eq!(msg.priority, 78);
+    }
+
+    #[test]
+    fn test_bare_pri() {
+        let opts = ParseOptions { allow_missing_pri: true, bare_pri: true, ..Default::default() };
+        let msg = parse_message_opts("13 Feb 18 20:53:31 2018 host app: msg", opts.clone())
+            .expect("Should parse message with bare PRI");
+        assert_eq!(msg.priority, 13);
+        assert_eq!(msg.facility, SyslogFacility::LOG_USER);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_NOTICE);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1518987211));
+        assert_eq!(msg.hostname, Some(String::from("host")));
+
+        let msg = parse_message_opts("78 Feb 18 20:53:31 2018 host app: msg", opts.clone()).unwrap();
+        assert_eq!(msg.facility, SyslogFacility::LOG_CRON);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_INFO);
+
+        let msg = parse_message_opts("Feb 18 20:53:31 2018 host app: msg", opts.clone())
+            .expect("Should parse message without PRI");
+        assert_eq!(msg.priority, MISSING_PRIORITY);
+        assert_eq!(msg.hostname, Some(String::from("host")));
+
+        let msg = parse_message_opts("<78>Feb 18 20:53:31 2018 host app: msg", opts.clone()).unwrap();
+        assert_eq!(msg.priority, 78);
+
+        // Too many digits, or not a valid PRI, isn't a bare PRI
+        assert!(parse_message_opts("1234 Feb 18 20:53:31 2018 host app: msg", opts.clone()).is_err());
+        assert!(parse_message_opts("999 Feb 18 20:53:31 2018 host app: msg", opts.clone()).is_err());
+
+        // An RFC5424 VERSION looks just like a bare PRI
+        let msg = parse_message_opts("2 2003-10-11T22:14:15Z host app - - - msg", opts.clone()).unwrap();
+        assert_eq!((msg.priority, msg.version), (2, 0));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "- - - msg");
+        assert_matches!(parse_message_opts("404 not found", opts),
+                        Err(ParseError { kind: ParseErr::TooFewDigits, .. }));
+
+        let opts = ParseOptions { allow_missing_pri: true, ..Default::default() };
+        let msg = parse_message_opts("2 2003-10-11T22:14:15Z host app - - - msg", opts.clone()).unwrap();
+        assert_eq!((msg.priority, msg.version), (MISSING_PRIORITY, 2));
+        assert_eq!(msg.msg, "msg");
+        assert!(parse_message_opts("404 not found", opts.clone()).is_err());
+        assert!(parse_message_opts("13 Feb 18 20:53:31 2018 host app: msg", opts).is_err());
+    }
+
+    #[test]
+    fn test_pri_override() {
+        fn vendor_pri(pri: i32) -> Option<(SyslogFacility, SyslogSeverity)> {
+            match pri {
+                // Facility 25, which doesn't exist
+                203 => Some((SyslogFacility::LOG_LOCAL7, SyslogSeverity::SEV_ERR)),
+                _ => None,
+            }
+        }
+        let input = "<203>Feb 18 20:53:31 2018 host app: msg";
+        assert_matches!(parse_message(input), Err(ParseError { kind: ParseErr::BadFacilityInPri(203), .. }));
+
+        let opts = ParseOptions { pri_override: Some(vendor_pri), ..Default::default() };
+        let msg = parse_message_opts(input, opts.clone()).expect("Should parse vendor PRI");
+        assert_eq!(msg.priority, 203);
+        assert_eq!(msg.facility, SyslogFacility::LOG_LOCAL7);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_ERR);
+        assert!(!msg.pri_recovered);
+
+        // Anything else is decoded as usual
+        let msg = parse_message_opts("<78>Feb 18 20:53:31 2018 host app: msg", opts.clone()).unwrap();
+        assert_eq!(msg.facility, SyslogFacility::LOG_CRON);
+        assert_matches!(parse_message_opts("<204>Feb 18 20:53:31 2018 host app: msg", opts),
+                        Err(ParseError { kind: ParseErr::BadFacilityInPri(204), .. }));
+    }
+
+    #[test]
+    fn test_monotonic_timestamp() {
+        let msg = parse_message("<4>[   12.345678] usb 1-1: new high-speed USB device number 2")
+            .expect("Should parse a dmesg-style line");
+        assert_eq!(msg.severity, SyslogSeverity::SEV_WARNING);
+        assert_eq!(msg.facility, SyslogFacility::LOG_KERN);
+        assert_eq!(msg.monotonic.map(|t| t.as_nanos()), Some(12345678000));
+        assert_eq!(msg.monotonic.map(|t| t.as_secs_f64()), Some(12.345678));
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.hostname, None);
+        assert_eq!(msg.tag, None);
+        assert_eq!(msg.msg, "usb 1-1: new high-speed USB device number 2");
+
+        let msg = parse_message("<6>[0] Booting Linux").expect("Should parse whole seconds");
+        assert_eq!(msg.monotonic.map(|t| t.as_nanos()), Some(0));
+        assert_eq!(msg.msg, "Booting Linux");
+
+        let msg = parse_message("<6>Feb 18 20:53:31 2018 host kernel: [   12.345678] eth0: link up").unwrap();
+        assert_eq!(msg.monotonic.map(|t| t.as_nanos()), Some(12345678000));
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.tag, Some(String::from("kernel")));
+        assert_eq!(msg.msg, "eth0: link up");
+        let msg = parse_message("<6>Feb 18 20:53:31 2018 host app: [   12.345678] not the kernel").unwrap();
+        assert_eq!(msg.monotonic, None);
+        assert_eq!(msg.msg, "[   12.345678] not the kernel");
+
+        assert!(parse_message("<4>[12.x] oops").is_err());
+        assert!(parse_message("<4>[   12.345678 oops").is_err());
+        assert!(parse_message_opts("<4>[   12.345678] oops",
+                                   ParseOptions { strict: true, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_fallback_raw_msg() {
+        let input = "<78 this is not syslog\n";
+        assert!(parse_message(input).is_err());
+
+        let opts = ParseOptions { fallback_raw_msg: true, ..Default::default() };
+        let msg = parse_message_opts(input, opts.clone()).expect("Should fall back to a raw message");
+        assert!(!msg.parsed_structure);
+        assert_eq!(msg.msg, "<78 this is not syslog");
+        assert_eq!(msg.facility, SyslogFacility::LOG_USER);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_NOTICE);
+        assert_eq!(msg.priority, MISSING_PRIORITY);
+        assert_eq!(msg.timestamp, None);
+        assert_eq!(msg.hostname, None);
+
+        // Messages that do parse are unaffected
+        let msg = parse_message_opts("<78>Feb 18 20:53:31 2018 host app: msg", opts.clone()).unwrap();
+        assert!(msg.parsed_structure);
+        assert_eq!(msg.msg, "msg");
+
+        let opts = ParseOptions { default_severity: Some(SyslogSeverity::SEV_WARNING), ..opts };
+        let msg = parse_message_opts("garbage", opts).unwrap();
+        assert_eq!(msg.severity, SyslogSeverity::SEV_WARNING);
+        assert!(!msg.parsed_structure);
+    }
+
+    #[test]
+    fn test_bom() {
+        let input = b"\xef\xbb\xbf<78>Jan 8 12:14:16 host1 CROND[123]: some_message";
+        let msg = parse_message_bytes(input).expect("Should skip the BOM");
+        assert_eq!(msg.priority, 78);
+        assert_eq!(msg.tag, Some(String::from("CROND")));
+        assert_eq!(msg.msg, "some_message");
+
+        let msg = parse_message("\u{feff}<34>1 2003-10-11T22:14:15Z host app - - - msg").expect("Should skip the BOM");
+        assert_eq!(msg.version, 1);
+
+        let opts = ParseOptions { skip_bom: false, ..Default::default() };
+        let err = parse_message_opts("\u{feff}<78>Jan 8 12:14:16 host1 CROND: msg", opts).unwrap_err();
+        assert_matches!(err.kind, ParseErr::ExpectedTokenErr('<'));
+
+        // Error offsets still count the BOM
+        let err = parse_message("\u{feff}<78Jan 8 12:14:16 host1 CROND msg").unwrap_err();
+        assert_eq!(err.offset, 6);
+    }
+
+    #[test]
+    fn test_pri_leading_zeros() {
+        for &(input, pri) in &[("<007>Jan 8 12:14:16 host - - -", 7),
+                               ("<0>Jan 8 12:14:16 host - - -", 0),
+                               ("<000>Jan 8 12:14:16 host - - -", 0),
+                               ("<0013>Jan 8 12:14:16 host - - -", 13)] {
+            let msg = parse_message(input).expect("Should parse message");
+            assert_eq!(msg.priority, pri);
+        }
+    }
+
+    #[test]
+    fn test_empty_pri() {
+        assert_matches!(parse_message("<>Jan 8 12:14:16 host - - -"),
+                        Err(ParseError { kind: ParseErr::EmptyPriority, .. }));
+    }
+
+    #[test]
+    fn test_max_len() {
+        let input = format!("<78>Jan 8 12:14:16 host1 123 CROND {}", "x".repeat(DEFAULT_MAX_LEN));
+        assert_matches!(parse_message(&input),
+                        Err(ParseError { kind: ParseErr::MessageTooLong, offset: DEFAULT_MAX_LEN, context: None }));
+
+        let opts = ParseOptions { max_len: Some(40), ..Default::default() };
+        assert!(parse_message_opts("<78>Jan 8 12:14:16 host1 123 CROND short", opts.clone()).is_ok());
+        assert_matches!(parse_message_opts("<78>Jan 8 12:14:16 host1 123 CROND too long", opts),
+                        Err(ParseError { kind: ParseErr::MessageTooLong, offset: 40, context: None }));
+
+        let opts = ParseOptions { max_len: None, ..Default::default() };
+        let msg = parse_message_opts(&input, opts).expect("Should parse long message");
+        assert_eq!(msg.msg.len(), DEFAULT_MAX_LEN);
     }
 
     #[test]
     fn test_bad_pri() {
         let msg = parse_message("<4096>Jan 8 12:14:16 - - - - - -");
         assert!(msg.is_err());
+
+        let msg = parse_message("<999>Jan 8 12:14:16 - - - - - -");
+        assert_matches!(msg, Err(ParseError { kind: ParseErr::BadFacilityInPri(999), .. }));
+    }
+
+    #[test]
+    fn test_seq_no() {
+        let msg = parse_message("123: <189>Feb 18 20:53:31 host %SYS-5-CONFIG_I: Configured from console")
+            .expect("Should parse message");
+        assert_eq!(msg.seq_no, Some(123));
+        assert_eq!(msg.facility, SyslogFacility::LOG_LOCAL7);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_NOTICE);
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.tag, Some(String::from("%SYS-5-CONFIG_I")));
+        assert_eq!(msg.msg, "Configured from console");
+        assert_eq!(msg.to_string(), "123: <189>Feb 18 20:53:31 host %SYS-5-CONFIG_I: Configured from console");
+
+        let msg = parse_message("<189>Feb 18 20:53:31 host %SYS-5-CONFIG_I: Configured from console")
+            .expect("Should parse message");
+        assert_eq!(msg.seq_no, None);
+        assert_eq!(msg.tag, Some(String::from("%SYS-5-CONFIG_I")));
+
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        assert_matches!(parse_message_opts("123: <189>Feb 18 20:53:31 host %SYS-5-CONFIG_I: Configured", opts),
+                        Err(ParseError { kind: ParseErr::ExpectedTokenErr('<'), offset: 0, .. }));
+    }
+
+    #[test]
+    fn test_highest_facility() {
+        let msg = parse_message("<191>Jan 8 12:14:16 host1 CROND: msg").expect("Should parse message");
+        assert_eq!(msg.facility, SyslogFacility::LOG_LOCAL7);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_DEBUG);
+
+        for pri in 192..256 {
+            let msg = parse_message(format!("<{}>Jan 8 12:14:16 host1 CROND: msg", pri));
+            assert_matches!(msg, Err(ParseError { kind: ParseErr::BadFacilityInPri(p), offset: 5, context: Some("PRI") }) if p == pri);
+        }
+    }
+
+    #[test]
+    fn test_message_ref() {
+        let input = String::from(r#"<78>Jan 8 12:14:16 2017 host1 123 CROND [meta sequenceId="29"] some_message"#);
+        let msg = parse_message_ref(&input).expect("Should parse message");
+        assert_eq!(msg.facility, SyslogFacility::LOG_CRON);
+        assert_eq!(msg.severity, SyslogSeverity::SEV_INFO);
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+        assert_eq!(msg.hostname, Some("host1"));
+        assert_eq!(msg.proc_id, Some("123"));
+        assert_eq!(msg.tag, Some("CROND"));
+        assert_eq!(msg.structured_data.len(), 1);
+        assert_eq!(msg.msg, "some_message");
+        // The string fields point into the input rather than being copied
+        assert_eq!(msg.msg.as_ptr(), input[input.len() - "some_message".len()..].as_ptr());
+
+        let owned = msg.into_owned();
+        assert_eq!(owned.hostname, Some(String::from("host1")));
+        assert_eq!(owned.proc_id, Some(ProcIdType::PID(123)));
+        assert_eq!(owned, parse_message(&input).expect("Should parse message"));
+    }
+
+    #[test]
+    fn test_year_was_inferred() {
+        let msg = parse_message("<78>Jan 8 12:14:16 host1 CROND: some_message").expect("Should parse message");
+        assert!(msg.timestamp.expect("Should have a timestamp").year_was_inferred());
+        let opts = ParseOptions { strict: true, ..Default::default() };
+        let msg = parse_message_opts("<78>Jan  8 12:14:16 host1 CROND: some_message", opts)
+            .expect("Should parse message");
+        assert!(msg.timestamp.expect("Should have a timestamp").year_was_inferred());
+
+        let msg = parse_message("<78>Jan 8 12:14:16 2017 host1 CROND: some_message").expect("Should parse message");
+        assert_eq!(msg.timestamp, Some(Timestamp::new(1483877656)));
+        assert!(!msg.timestamp.expect("Should have a timestamp").year_was_inferred());
+        let msg = parse_message("<34>2003-10-11T22:14:15Z mymachine su: 'su root' failed").expect("Should parse message");
+        assert_eq!(msg.timestamp, Some(Timestamp::new(1065910455)));
+        let msg = parse_message("<34>1 2003-10-11T22:14:15Z host su 1234 - - msg").expect("Should parse message");
+        assert_eq!(msg.timestamp, Some(Timestamp::new(1065910455)));
+
+        // Serialized as just the seconds, either way
+        let msg = parse_message_ref_in_year("<78>Jan 8 12:14:16 host1 CROND: some_message", 2017)
+            .expect("Should parse message")
+            .into_owned();
+        assert_eq!(msg.timestamp, Some(Timestamp::with_inferred_year(1483877656)));
+        assert!(msg.timestamp.expect("Should have a timestamp").year_was_inferred());
+        let json = serde_json::to_string(&msg).expect("Should encode to JSON");
+        assert!(json.contains("\"timestamp\":1483877656,"));
+    }
+
+    #[test]
+    fn test_message_ref_in_year() {
+        let msg = parse_message_ref_in_year("<78>Dec 31 23:59:00 host1 CROND: some_message", 2016)
+            .expect("Should parse message");
+        // 2016-12-31T23:59:00Z
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483228740));
+        let msg = parse_message_ref_in_year("<78>Jan 8 12:14:16 host1 CROND: some_message", 2017)
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+        // An explicit year wins
+        let msg = parse_message_ref_in_year("<78>Jan 8 12:14:16 2017 host1 CROND: some_message", 2000)
+            .expect("Should parse message");
+        assert_eq!(msg.timestamp.map(|ts| ts.secs()), Some(1483877656));
+
+        // chrono's calendar only covers about 262,000 years, and a year outside it is an error
+        for &year in &[i32::MAX, 300000, i32::MIN] {
+            let res = parse_message_ref_in_year("<78>Jan 8 12:14:16 host1 CROND: some_message", year);
+            if cfg!(feature = "chrono") {
+                assert_matches!(res, Err(ParseError { kind: ParseErr::InvalidDateField("year"),
+                                                      context: Some("timestamp"), .. }));
+            } else {
+                assert!(res.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_bytes_with_invalid_utf8_body() {
+        let msg = parse_message_bytes(b"<13>Feb 18 20:53:31 host 123 app caf\xe9 au lait")
+            .expect("Should parse message with latin-1 body");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "caf\u{fffd} au lait");
+    }
+
+    #[test]
+    fn test_bytes_with_invalid_utf8_header() {
+        let msg = parse_message_bytes(b"<13>Feb 18 20:53:31 ho\xffst 123 app hi");
+        assert!(msg.is_err());
+    }
+
+    #[test]
+    fn test_bytes_with_invalid_utf8_tag() {
+        // A Latin-1 tag isn't silently dropped
+        let err = parse_message_bytes(b"<13>Feb 18 20:53:31 host caf\xe9[123]: hi").unwrap_err();
+        assert_matches!(err.kind, ParseErr::BaseUnicodeError(_));
+        assert_eq!(err.offset, 28);
+
+        let msg = parse_message_bytes(b"<13>Feb 18 20:53:31 host app[123]: caf\xe9: hi")
+            .expect("Should parse message with latin-1 body");
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "caf\u{fffd}: hi");
+
+        // The tag's colon ends the header, even without a space after it
+        let msg = parse_message_bytes(b"<13>Feb 18 20:53:31 host app:\xe9")
+            .expect("Should parse message with latin-1 body right after the tag");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.tag, Some(String::from("app")));
+        assert_eq!(msg.msg, "\u{fffd}");
+    }
+
+    #[test]
+    fn test_framed() {
+        let input = b"35 <13>Feb 18 20:53:31 host 123 app hi38 <14>Feb 18 20:53:31 host 123 app there<15>";
+        let (msg, rest) = parse_framed(input).expect("Should parse first frame");
+        assert_eq!(msg.priority, 13);
+        assert_eq!(msg.msg, "hi");
+        let (msg, rest) = parse_framed(rest).expect("Should parse second frame");
+        assert_eq!(msg.priority, 14);
+        assert_eq!(msg.msg, "there");
+        assert_eq!(rest, b"<15>");
+    }
+
+    #[test]
+    fn test_framed_incomplete() {
+        let err = parse_framed(b"35 <13>Feb 18 20:53:31 host 123").unwrap_err();
+        assert_matches!(err.kind, ParseErr::IncompleteFrame(7));
+
+        let err = parse_framed(b"39").unwrap_err();
+        assert_matches!(err.kind, ParseErr::UnexpectedEndOfInput);
+    }
+
+    #[test]
+    fn test_framed_errors() {
+        let err = parse_framed(b"<13>Feb 18 20:53:31 host 123 app hi").unwrap_err();
+        assert_matches!(err.kind, ParseErr::TooFewDigits);
+
+        let err = parse_framed(b"35<13>Feb 18 20:53:31 host 123 app hi").unwrap_err();
+        assert_matches!(err.kind, ParseErr::ExpectedTokenErr(' '));
+
+        // Offsets of errors inside the frame are relative to the whole input
+        let err = parse_framed(b"6 <13Feb").unwrap_err();
+        assert_matches!(err.kind, ParseErr::ExpectedTokenErr('>'));
+        assert_eq!(err.offset, 5);
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let input = "<78>Mar 15 14:16:22 host1 CROND[1]: first\r\n\
+                     <78>Mar 15 14:16:23 host1\n\
+                     \n\
+                     <78>Mar 15 14:16:24 host1 CROND[3]: third\n\
+                     <999>Mar 15 14:16:25 host1 CROND[4]: fourth";
+        let (messages, errors) = parse_all(input);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].msg, "first");
+        assert_eq!(messages[1].msg, "third");
+        assert_eq!(errors.len(), 2);
+        assert_matches!(errors[0], (2, ParseError { kind: ParseErr::UnexpectedEndOfInput, .. }));
+        assert_matches!(errors[1], (5, ParseError { kind: ParseErr::BadFacilityInPri(999), .. }));
+
+        let (messages, errors) = parse_all("");
+        assert!(messages.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_decoder() {
+        let mut decoder = Rfc3164Decoder::new();
+        let mut buf = Vec::new();
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+
+        // A message split across several reads
+        buf.extend_from_slice(b"<13>Feb 18 20:53:31 ho");
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+        buf.extend_from_slice(b"st sshd[12]: hi");
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+        buf.extend_from_slice(b"\r\n\n<14>Feb 18 20:53:32 host app: bye\n<15>Feb");
+        let msg = decoder.decode(&mut buf).expect("Should decode").expect("Should have a message");
+        assert_eq!(msg.hostname, Some(String::from("host")));
+        assert_eq!(msg.msg, "hi");
+        let msg = decoder.decode(&mut buf).expect("Should decode").expect("Should have a message");
+        assert_eq!(msg.priority, 14);
+        assert_eq!(msg.msg, "bye");
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+        assert_eq!(buf, b"<15>Feb");
+
+        // A bad message is dropped, and decoding picks up again after it
+        buf.extend_from_slice(b"oops\n<16>Feb 18 20:53:33 host app: ok\n");
+        assert!(decoder.decode(&mut buf).is_err());
+        let msg = decoder.decode(&mut buf).expect("Should decode").expect("Should have a message");
+        assert_eq!(msg.priority, 16);
+        assert!(buf.is_empty());
+
+        let mut decoder = Rfc3164Decoder::with_options(ParseOptions { max_len: Some(16), ..Default::default() });
+        buf.extend_from_slice(b"<13>Feb 18 20:53:31 host");
+        assert_matches!(decoder.decode(&mut buf), Err(ParseError { kind: ParseErr::MessageTooLong, .. }));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_shrunk_buffer() {
+        let mut decoder = Rfc3164Decoder::new();
+        let mut buf = b"<13>Feb 18 20:53:31 host sshd[12]: an incomplete message".to_vec();
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+
+        // Throwing the partial message away mustn't trip up the next call
+        buf.clear();
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+        buf.extend_from_slice(b"<14>Feb 18 20:53:32 host app: bye\n");
+        let msg = decoder.decode(&mut buf).expect("Should decode").expect("Should have a message");
+        assert_eq!(msg.priority, 14);
+        buf.extend_from_slice(b"<15>");
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+        buf.truncate(1);
+        assert!(decoder.decode(&mut buf).expect("Should decode").is_none());
+    }
+
+    #[test]
+    fn test_error_offset() {
+        let err = parse_message("<78Jan 8 12:14:16 host1 CROND msg").unwrap_err();
+        assert_matches!(err.kind, ParseErr::ExpectedTokenErr('>'));
+        assert_eq!(err.offset, 3);
+
+        let err = parse_message("<78>Jan 8 12:14").unwrap_err();
+        assert_matches!(err.kind, ParseErr::UnexpectedEndOfInput);
+        assert_eq!(err.offset, 4);
     }
 
     #[test]
@@ -385,6 +3498,38 @@ mod tests {
         // we should be able to parse RFC3164 messages
         let msg = parse_message("<134>Feb 18 20:53:31 hostname.local nginx: I am a message");
         assert!(!msg.is_err());
+        let msg = msg.unwrap();
+        assert_eq!(msg.tag, Some(String::from("nginx")));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.msg, "I am a message");
+    }
+
+    #[test]
+    fn test_tag_with_pid() {
+        let msg = parse_message("<38>Feb 18 20:53:31 host1 sshd[1234]: Accepted publickey")
+            .expect("Should parse message");
+        assert_eq!(msg.hostname, Some(String::from("host1")));
+        assert_eq!(msg.tag, Some(String::from("sshd")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::PID(1234)));
+        assert_eq!(msg.msg, "Accepted publickey");
+
+        let msg = parse_message("<38>Feb 18 20:53:31 host1 sshd: Accepted publickey")
+            .expect("Should parse message");
+        assert_eq!(msg.tag, Some(String::from("sshd")));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.msg, "Accepted publickey");
+
+        let msg = parse_message("<38>Feb 18 20:53:31 host1 sshd Accepted publickey")
+            .expect("Should parse message");
+        assert_eq!(msg.tag, Some(String::from("sshd")));
+        assert_eq!(msg.proc_id, None);
+        assert_eq!(msg.msg, "Accepted publickey");
+
+        let msg = parse_message("<38>Feb 18 20:53:31 host1 postfix/smtpd[worker-1] connect")
+            .expect("Should parse message");
+        assert_eq!(msg.tag, Some(String::from("postfix/smtpd")));
+        assert_eq!(msg.proc_id, Some(ProcIdType::Name(String::from("worker-1"))));
+        assert_eq!(msg.msg, "connect");
     }
 
     #[test]
@@ -393,4 +3538,50 @@ mod tests {
         let msg = parse_message("<190>May 13 21:45:18 coconut hotdog: hi");
         assert!(!msg.is_err());
     }
+
+    #[test]
+    fn test_arbitrary_input_does_not_panic() {
+        // A small xorshift PRNG, so that failures are reproducible without pulling in a fuzzer
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let seeds: &[&[u8]] = &[
+            b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed",
+            b"<165>1 2003-08-24T05:14:15.000003-07:00 192.0.2.1 myproc 8710 - [a b=\"c\"] msg",
+            b"<13>Feb  5 17:32:18.123 2017 +01:00 [::1] sshd[1234]: hi",
+            b"35 <13>Feb 18 20:53:31 host 123 app hi",
+        ];
+        // Syntactically interesting bytes, plus the pieces of some multi-byte characters
+        let alphabet = b"<>[]:-+. 0123456789TZ\"\\=\n\x00\xc3\xa9\xe2\x82\xac\xf0\x9f\x92\xa9\xff";
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        for _ in 0..20000 {
+            let mut input = seeds[next() as usize % seeds.len()].to_vec();
+            for _ in 0..(next() % 8) {
+                let pos = next() as usize % (input.len() + 1);
+                let byte = if next() % 2 == 0 {
+                    alphabet[next() as usize % alphabet.len()]
+                } else {
+                    next() as u8
+                };
+                match next() % 3 {
+                    0 => input.insert(pos, byte),
+                    1 if pos < input.len() => input[pos] = byte,
+                    _ if pos < input.len() => { input.remove(pos); },
+                    _ => input.push(byte),
+                }
+            }
+            if let Ok(msg) = parse_message_bytes(&input) {
+                let _ = msg.to_string();
+            }
+            let _ = parse_framed(&input);
+            let text = String::from_utf8_lossy(&input);
+            let _ = parse_message(&*text);
+            let _ = parse_message_opts(&*text, strict.clone());
+            let _ = parse_message_partial(&text);
+        }
+    }
 }
@@ -1,8 +1,16 @@
-use serde::{Serializer, Serialize};
+use core::fmt;
+use core::str::FromStr;
 
-#[derive(Copy,Clone,Debug,PartialEq)]
+use log;
+use serde::{Deserialize, Deserializer, Serializer, Serialize};
+use serde::de::{self, Unexpected, Visitor};
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
 #[allow(non_camel_case_types)]
 /// Syslog Severities from RFC 5424.
+///
+/// Severities are ordered by their ints, so the most severe compares lowest: `SEV_EMERG <
+/// SEV_ALERT < ... < SEV_DEBUG`.
 pub enum SyslogSeverity {
     SEV_EMERG = 0,
     SEV_ALERT = 1,
@@ -14,6 +22,12 @@ pub enum SyslogSeverity {
     SEV_DEBUG = 7,
 }
 
+const ALL_SEVERITIES: [SyslogSeverity; 8] = [
+    SyslogSeverity::SEV_EMERG, SyslogSeverity::SEV_ALERT, SyslogSeverity::SEV_CRIT,
+    SyslogSeverity::SEV_ERR, SyslogSeverity::SEV_WARNING, SyslogSeverity::SEV_NOTICE,
+    SyslogSeverity::SEV_INFO, SyslogSeverity::SEV_DEBUG,
+];
+
 impl SyslogSeverity {
     /// Convert an int (as used in the wire serialization) into a `SyslogSeverity`
     ///
@@ -33,6 +47,22 @@ impl SyslogSeverity {
         }
     }
 
+    /// Convert a `SyslogSeverity` into its int (as used in the wire serialization); the inverse of
+    /// `from_int`
+    pub fn as_int(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Every severity, in order of their ints
+    pub fn all() -> &'static [SyslogSeverity] {
+        &ALL_SEVERITIES
+    }
+
+    /// The severity's lowercase name, as in JSON, without going through serde; the same as `as_str`
+    pub fn text(&self) -> &'static str {
+        self.as_str()
+    }
+
     /// Convert a syslog severity into a unique string representation
     pub fn as_str(&self) -> &'static str {
         match *self {
@@ -46,6 +76,42 @@ impl SyslogSeverity {
             SyslogSeverity::SEV_DEBUG => "debug"
         }
     }
+
+    /// Whether this severity is `min` or more severe, e.g. for only forwarding errors and worse
+    ///
+    /// More severe means a lower int, so `SEV_ERR.is_at_least(SEV_WARNING)` is true, but
+    /// `SEV_INFO.is_at_least(SEV_WARNING)` is false.
+    pub fn is_at_least(&self, min: SyslogSeverity) -> bool {
+        *self <= min
+    }
+
+    /// The `log` crate level to re-log a message of this severity at
+    ///
+    /// `log` has fewer levels than syslog, so `emerg`, `alert` and `crit` all collapse into
+    /// `Error`, and `notice` into `Info`. Nothing maps to `Trace`.
+    pub fn to_log_level(&self) -> log::Level {
+        match *self {
+            SyslogSeverity::SEV_EMERG
+            | SyslogSeverity::SEV_ALERT
+            | SyslogSeverity::SEV_CRIT
+            | SyslogSeverity::SEV_ERR => log::Level::Error,
+            SyslogSeverity::SEV_WARNING => log::Level::Warn,
+            SyslogSeverity::SEV_NOTICE | SyslogSeverity::SEV_INFO => log::Level::Info,
+            SyslogSeverity::SEV_DEBUG => log::Level::Debug,
+        }
+    }
+}
+
+impl FromStr for SyslogSeverity {
+    type Err = ();
+
+    /// Look up a severity by the name returned from `as_str` (which is also what it serializes as)
+    fn from_str(s: &str) -> Result<Self, ()> {
+        SyslogSeverity::all().iter()
+            .find(|v| v.as_str() == s)
+            .cloned()
+            .ok_or(())
+    }
 }
 
 impl Serialize for SyslogSeverity {
@@ -54,10 +120,48 @@ impl Serialize for SyslogSeverity {
     }
 }
 
+struct SyslogSeverityVisitor;
+
+impl<'de> Visitor<'de> for SyslogSeverityVisitor {
+    type Value = SyslogSeverity;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a syslog severity name")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<SyslogSeverity, E> {
+        v.parse().map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for SyslogSeverity {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_str(SyslogSeverityVisitor)
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::SyslogSeverity;
+    use serde_json;
+    use log::Level;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_text() {
+        for v in SyslogSeverity::all() {
+            assert_eq!(serde_json::to_string(v).unwrap(), format!("\"{}\"", v.text()));
+            assert_eq!(v.text(), v.as_str());
+        }
+    }
+
+    #[test]
+    fn test_as_int() {
+        for i in 0..8 {
+            assert_eq!(SyslogSeverity::from_int(i).expect("valid severity").as_int(), i);
+        }
+    }
 
     #[test]
     fn test_deref() {
@@ -70,4 +174,55 @@ mod tests {
         assert_eq!(SyslogSeverity::SEV_INFO.as_str(), "info");
         assert_eq!(SyslogSeverity::SEV_DEBUG.as_str(), "debug");
     }
+
+    #[test]
+    fn test_all() {
+        assert_eq!(SyslogSeverity::all().len(), 8);
+        for (i, v) in SyslogSeverity::all().iter().enumerate() {
+            assert_eq!(v.as_int(), i as i32);
+            assert_eq!(SyslogSeverity::from_str(v.text()), Ok(*v));
+        }
+    }
+
+    #[test]
+    fn test_ord() {
+        assert!(SyslogSeverity::SEV_EMERG < SyslogSeverity::SEV_ALERT);
+        assert!(SyslogSeverity::SEV_INFO < SyslogSeverity::SEV_DEBUG);
+        let mut sorted = SyslogSeverity::all().to_vec();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, SyslogSeverity::all());
+    }
+
+    #[test]
+    fn test_is_at_least() {
+        assert!(SyslogSeverity::SEV_ERR.is_at_least(SyslogSeverity::SEV_WARNING));
+        assert!(SyslogSeverity::SEV_EMERG.is_at_least(SyslogSeverity::SEV_WARNING));
+        assert!(SyslogSeverity::SEV_WARNING.is_at_least(SyslogSeverity::SEV_WARNING));
+        assert!(!SyslogSeverity::SEV_INFO.is_at_least(SyslogSeverity::SEV_WARNING));
+        assert!(!SyslogSeverity::SEV_DEBUG.is_at_least(SyslogSeverity::SEV_EMERG));
+    }
+
+    #[test]
+    fn test_to_log_level() {
+        assert_eq!(SyslogSeverity::SEV_EMERG.to_log_level(), Level::Error);
+        assert_eq!(SyslogSeverity::SEV_ALERT.to_log_level(), Level::Error);
+        assert_eq!(SyslogSeverity::SEV_CRIT.to_log_level(), Level::Error);
+        assert_eq!(SyslogSeverity::SEV_ERR.to_log_level(), Level::Error);
+        assert_eq!(SyslogSeverity::SEV_WARNING.to_log_level(), Level::Warn);
+        assert_eq!(SyslogSeverity::SEV_NOTICE.to_log_level(), Level::Info);
+        assert_eq!(SyslogSeverity::SEV_INFO.to_log_level(), Level::Info);
+        assert_eq!(SyslogSeverity::SEV_DEBUG.to_log_level(), Level::Debug);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let names = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(SyslogSeverity::from_str(name), Ok(SyslogSeverity::from_int(i as i32).unwrap()));
+        }
+        assert_eq!("info".parse(), Ok(SyslogSeverity::SEV_INFO));
+        assert_eq!(SyslogSeverity::from_str("INFO"), Err(()));
+        assert_eq!(SyslogSeverity::from_str("information"), Err(()));
+    }
 }